@@ -75,13 +75,32 @@ fn generate_pike_bindings()
         .whitelist_function("aggregate_array")
         .whitelist_function("real_allocate_array")
         .whitelist_function("append_array")
+        .whitelist_function("array_insert")
+        .whitelist_function("array_remove")
+        .whitelist_function("array_shrink")
+        .whitelist_function("slice_array")
 
         .whitelist_function("f_aggregate_mapping")
         .whitelist_function("mapping_insert")
         .whitelist_function("debug_allocate_mapping")
+        .whitelist_function("low_mapping_lookup")
+        .whitelist_function("low_mapping_string_lookup")
+        .whitelist_function("map_delete_no_free")
+        .whitelist_function("mapping_indices")
+        .whitelist_function("mapping_values")
 
-        .whitelist_function("f_get_iterator")
         .whitelist_function("multiset_sizeof")
+        .whitelist_function("multiset_indices")
+        .whitelist_function("allocate_multiset")
+        .whitelist_function("multiset_insert")
+        .whitelist_function("multiset_delete")
+        .whitelist_function("multiset_lookup")
+        .whitelist_function("multiset_first")
+        .whitelist_function("multiset_next")
+        .whitelist_function("do_sub_msnode_ref")
+        .whitelist_var("MULTISET_INDVAL")
+        .whitelist_var("MULTISET_FLAG_MASK")
+        .whitelist_var("T_DELETED")
 
         .whitelist_function("really_free_.*")
         .whitelist_function("schedule_really_free_object")
@@ -91,8 +110,6 @@ fn generate_pike_bindings()
         .whitelist_function("debug_master")
 
         .whitelist_var("[a-z]*_type_string")
-        .whitelist_function("f_string_to_utf8")
-        .whitelist_function("f_utf8_to_string")
 
         .whitelist_type("visit_thing_fn")
         .whitelist_type("visit_enter_cb")
@@ -121,6 +138,12 @@ fn generate_pike_bindings()
         .whitelist_function("add_program_constant")
         .whitelist_function("debug_clone_object")
         .whitelist_function("program_index_no_free")
+        .whitelist_function("object_index_no_free")
+        .whitelist_function("object_set_index")
+        .whitelist_function("object_indices")
+        .whitelist_function("object_values")
+        .whitelist_function("low_get_storage")
+        .whitelist_function("find_identifier")
 
         .whitelist_function("pike_threads_allow")
         .whitelist_function("pike_threads_disallow")
@@ -131,9 +154,22 @@ fn generate_pike_bindings()
         .whitelist_var("PROG_EVENT_.*")
 
         .whitelist_function("debug_make_shared_.*")
+        .whitelist_function("string_slice")
+        .whitelist_function("add_shared_strings")
 
         .whitelist_var("OPT_.*")
+        .whitelist_function("pike_types_le")
+        .whitelist_function("match_types")
+        .whitelist_function("get_type_of_svalue")
+        .whitelist_function("parse_type")
+
         .whitelist_var("PIKE_T_.*")
+        .whitelist_var("T_VOID")
+        .whitelist_var("T_MANY")
+        .whitelist_var("T_ASSIGN")
+        .whitelist_var("T_NOT")
+        .whitelist_var("T_AND")
+        .whitelist_var("T_OR")
 
         .whitelist_var("Pike_interpreter_pointer");
 
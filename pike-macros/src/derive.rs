@@ -0,0 +1,450 @@
+// Implementation of `#[derive(ToPike)]` and `#[derive(FromPike)]`. Structs
+// are converted to mappings (or arrays for tuple structs), and enums are
+// externally tagged: unit variants become strings, and other variants become
+// ([ "Variant": content ]).
+
+use quote;
+use syn;
+
+// The default value of a field, used if its key is missing from a mapping,
+// or its element from the end of an array.
+enum FieldDefault {
+    None,
+    Trait,
+    Path(syn::Path)
+}
+
+// The options of a field or variant from its #[pike(...)] attributes.
+struct PikeAttrs {
+    rename: Option<String>,
+    default: FieldDefault,
+    skip: bool
+}
+
+fn parse_attrs(attrs: &[syn::Attribute]) -> PikeAttrs {
+    let mut res = PikeAttrs {
+        rename: None,
+        default: FieldDefault::None,
+        skip: false
+    };
+    for attr in attrs {
+        let list = match attr.interpret_meta() {
+            Some(syn::Meta::List(ref list)) if list.ident.as_ref() == "pike" => {
+                list.clone()
+            },
+            _ => continue
+        };
+        for nested in list.nested.iter() {
+            match nested {
+                syn::NestedMeta::Meta(syn::Meta::Word(word)) => {
+                    match word.as_ref() {
+                        "skip" => res.skip = true,
+                        "default" => res.default = FieldDefault::Trait,
+                        other => panic!("Unknown pike attribute `{}`", other)
+                    }
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => {
+                    let value = match nv.lit {
+                        syn::Lit::Str(ref s) => s.value(),
+                        _ => panic!("The value of pike attribute `{}` must \
+                            be a string", nv.ident)
+                    };
+                    match nv.ident.as_ref() {
+                        "rename" => res.rename = Some(value),
+                        "default" => {
+                            let path = syn::parse_str(&value)
+                                .expect("Invalid path in pike default attribute");
+                            res.default = FieldDefault::Path(path);
+                        },
+                        other => panic!("Unknown pike attribute `{}`", other)
+                    }
+                },
+                _ => panic!("Unsupported pike attribute")
+            }
+        }
+    }
+    res
+}
+
+// A field and the expression or binding used to access it.
+struct FieldInfo {
+    attrs: PikeAttrs,
+    key: String,
+    access: quote::Tokens
+}
+
+// Collects the fields, accessed with access(index, ident).
+fn field_infos<F>(fields: &syn::Fields, access: F) -> Vec<FieldInfo>
+where F: Fn(usize, Option<syn::Ident>) -> quote::Tokens {
+    fields.iter().enumerate().map(|(index, field)| {
+        let attrs = parse_attrs(&field.attrs);
+        let key = match (&attrs.rename, field.ident) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(ident)) => ident.to_string(),
+            (None, None) => index.to_string()
+        };
+        FieldInfo { attrs, key, access: access(index, field.ident) }
+    }).collect()
+}
+
+// Returns an expression that converts the fields to a PikeThing.
+fn fields_to_pike(fields: &syn::Fields, infos: &[FieldInfo]) -> quote::Tokens {
+    match fields {
+        syn::Fields::Named(_) => {
+            let inserts = infos.iter().filter(|info| !info.attrs.skip)
+                .map(|info| {
+                    let key = &info.key;
+                    let access = &info.access;
+                    quote! { m.insert_field(#key, #access)?; }
+                });
+            let len = infos.len();
+            quote! {{
+                let m = PikeMapping::with_capacity(#len, ctx);
+                #(#inserts)*
+                Ok(m.into())
+            }}
+        },
+        syn::Fields::Unnamed(_) if infos.len() == 1 => {
+            assert!(!infos[0].attrs.skip, "The field of a newtype can't be skipped");
+            let access = &infos[0].access;
+            quote! { ToPike::to_pike(#access, ctx) }
+        },
+        // Skipped fields are left out of the array.
+        syn::Fields::Unnamed(_) => {
+            let appends = infos.iter().filter(|info| !info.attrs.skip)
+                .enumerate().map(|(index, info)| {
+                    let access = &info.access;
+                    quote! {
+                        a.append(ToPike::to_pike(#access, ctx)
+                            .map_err(|e| e.at_index(#index))?);
+                    }
+                });
+            let len = infos.iter().filter(|info| !info.attrs.skip).count();
+            quote! {{
+                let mut a = PikeArray::with_capacity(#len, ctx);
+                #(#appends)*
+                Ok(a.into())
+            }}
+        },
+        syn::Fields::Unit => {
+            quote! { Ok(PikeThing::undefined()) }
+        }
+    }
+}
+
+// Returns the expression for the default value of a field, if it has one.
+fn default_value(info: &FieldInfo) -> Option<quote::Tokens> {
+    match info.attrs.default {
+        _ if info.attrs.skip => Some(quote! { Default::default() }),
+        FieldDefault::None => None,
+        FieldDefault::Trait => Some(quote! { Default::default() }),
+        FieldDefault::Path(ref path) => Some(quote! { #path() })
+    }
+}
+
+// Returns an expression that converts the PikeThing in `thing` to the struct
+// or variant at path.
+fn fields_from_pike(fields: &syn::Fields, infos: &[FieldInfo],
+    path: quote::Tokens, desc: &str) -> quote::Tokens {
+    match fields {
+        syn::Fields::Named(_) => {
+            let inits = infos.iter().map(|info| {
+                let ident = &info.access;
+                let key = &info.key;
+                let value = match (info.attrs.skip, &info.attrs.default) {
+                    (true, _) => quote! { Default::default() },
+                    (false, FieldDefault::None) => quote! { m.field(#key)? },
+                    (false, FieldDefault::Trait) => quote! {
+                        m.optional_field(#key)?.unwrap_or_else(Default::default)
+                    },
+                    (false, FieldDefault::Path(path)) => quote! {
+                        m.optional_field(#key)?.unwrap_or_else(#path)
+                    }
+                };
+                quote! { #ident: #value }
+            });
+            quote! {{
+                let m: PikeMapping = match thing {
+                    PikeThing::Mapping(m_ref) => m_ref.into_with_ctx(ctx),
+                    ref other => return Err(ConversionError::new(format!(
+                        "Expected a mapping for {}, got {}.", #desc,
+                        other.type_name())))
+                };
+                Ok(#path { #(#inits),* })
+            }}
+        },
+        // A newtype with a default uses it for UNDEFINED, like a missing key.
+        syn::Fields::Unnamed(_) if infos.len() == 1 => {
+            assert!(!infos[0].attrs.skip, "The field of a newtype can't be skipped");
+            match default_value(&infos[0]) {
+                Some(default) => quote! {
+                    match thing {
+                        PikeThing::Undefined => Ok(#path(#default)),
+                        thing => Ok(#path(FromPike::from_pike(thing, ctx)?))
+                    }
+                },
+                None => quote! { Ok(#path(FromPike::from_pike(thing, ctx)?)) }
+            }
+        },
+        // Skipped fields aren't in the array, and fields with defaults use
+        // them if the array is too short to contain them.
+        syn::Fields::Unnamed(_) => {
+            let mut index = 0usize;
+            let mut min_len = 0usize;
+            let mut elems = Vec::new();
+            for info in infos {
+                if info.attrs.skip {
+                    elems.push(quote! { Default::default() });
+                    continue;
+                }
+                let conversion = quote! {
+                    FromPike::from_pike(a.get(#index)?, ctx)
+                        .map_err(|e| e.at_index(#index))?
+                };
+                elems.push(match default_value(info) {
+                    Some(default) => quote! {
+                        if a.len() > #index { #conversion } else { #default }
+                    },
+                    None => {
+                        min_len = index + 1;
+                        conversion
+                    }
+                });
+                index += 1;
+            }
+            let max_len = index;
+            let expected = if min_len == max_len {
+                max_len.to_string()
+            } else {
+                format!("{} to {}", min_len, max_len)
+            };
+            quote! {{
+                let a: PikeArray = match thing {
+                    PikeThing::Array(a_ref) => a_ref.into_with_ctx(ctx),
+                    ref other => return Err(ConversionError::new(format!(
+                        "Expected an array for {}, got {}.", #desc,
+                        other.type_name())))
+                };
+                if a.len() < #min_len || a.len() > #max_len {
+                    return Err(ConversionError::new(format!(
+                        "Expected {} elements for {}, got {}.", #expected, #desc,
+                        a.len())));
+                }
+                Ok(#path(#(#elems),*))
+            }}
+        },
+        syn::Fields::Unit => {
+            quote! {{
+                let _ = thing;
+                Ok(#path)
+            }}
+        }
+    }
+}
+
+// The name of a variant in Pike, and the bindings of its fields.
+fn variant_infos(variant: &syn::Variant) -> (String, Vec<FieldInfo>) {
+    let attrs = parse_attrs(&variant.attrs);
+    let name = attrs.rename.unwrap_or_else(|| variant.ident.to_string());
+    let infos = field_infos(&variant.fields, |index, ident| {
+        match ident {
+            Some(ident) => quote! { #ident },
+            None => {
+                let binding = syn::Ident::from(format!("__field{}", index));
+                quote! { #binding }
+            }
+        }
+    });
+    (name, infos)
+}
+
+// Returns a pattern that matches the variant and binds its fields. Skipped
+// fields aren't bound.
+fn variant_pattern(enum_ident: &syn::Ident, variant: &syn::Variant,
+    infos: &[FieldInfo]) -> quote::Tokens {
+    let ident = variant.ident;
+    let bindings = infos.iter().zip(variant.fields.iter()).map(|(info, field)| {
+        let access = &info.access;
+        match (info.attrs.skip, field.ident) {
+            (true, Some(field_ident)) => quote! { #field_ident: _ },
+            (true, None) => quote! { _ },
+            (false, _) => quote! { ref #access }
+        }
+    });
+    match variant.fields {
+        syn::Fields::Named(_) => quote! { #enum_ident::#ident { #(#bindings),* } },
+        syn::Fields::Unnamed(_) => quote! { #enum_ident::#ident(#(#bindings),*) },
+        syn::Fields::Unit => quote! { #enum_ident::#ident }
+    }
+}
+
+// Adds the trait as a bound on each type parameter.
+// The names used by the generated code, imported by their full paths within
+// the generated functions, so that the deriving crate doesn't have to import
+// them.
+fn imports() -> quote::Tokens {
+    quote! {
+        #[allow(unused_imports)]
+        use ::pike_bindings::traits::{ToPike, FromPike, IntoWithCtx};
+        #[allow(unused_imports)]
+        use ::pike_bindings::types::{PikeThing, PikeString, PikeArray,
+            PikeMapping};
+        #[allow(unused_imports)]
+        use ::pike_bindings::serialization::Error as ConversionError;
+    }
+}
+
+fn add_trait_bounds(mut generics: syn::Generics, bound: &str) -> syn::Generics {
+    for param in generics.params.iter_mut() {
+        if let syn::GenericParam::Type(ref mut type_param) = *param {
+            type_param.bounds.push(syn::parse_str(bound).unwrap());
+        }
+    }
+    generics
+}
+
+pub fn derive_to_pike(input: syn::DeriveInput) -> quote::Tokens {
+    let ident = input.ident;
+    let body = match input.data {
+        syn::Data::Struct(ref data) => {
+            let infos = field_infos(&data.fields, |index, field_ident| {
+                match field_ident {
+                    Some(field_ident) => quote! { &self.#field_ident },
+                    None => {
+                        let index = syn::Index::from(index);
+                        quote! { &self.#index }
+                    }
+                }
+            });
+            fields_to_pike(&data.fields, &infos)
+        },
+        syn::Data::Enum(ref data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let (name, infos) = variant_infos(variant);
+                let pattern = variant_pattern(&ident, variant, &infos);
+                let content = fields_to_pike(&variant.fields, &infos);
+                match variant.fields {
+                    syn::Fields::Unit => quote! {
+                        #pattern => Ok(PikeString::from_str_slice(#name, ctx).into())
+                    },
+                    _ => quote! {
+                        #pattern => {
+                            let content: Result<PikeThing, ConversionError> =
+                                #content;
+                            let m = PikeMapping::with_capacity(1, ctx);
+                            m.insert(PikeString::from_str_slice(#name, ctx).into(),
+                                content.map_err(|e| e.at_key(#name))?);
+                            Ok(m.into())
+                        }
+                    }
+                }
+            });
+            quote! {
+                match *self {
+                    #(#arms),*
+                }
+            }
+        },
+        syn::Data::Union(_) => panic!("Unions can't derive ToPike")
+    };
+
+    let generics = add_trait_bounds(input.generics.clone(),
+        "::pike_bindings::traits::ToPike");
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let imports = imports();
+    quote! {
+        impl #impl_generics ::pike_bindings::traits::ToPike
+        for #ident #ty_generics #where_clause {
+            fn to_pike(&self, ctx: &::pike_bindings::interpreter::PikeContext)
+            -> Result<::pike_bindings::types::PikeThing,
+                ::pike_bindings::serialization::Error> {
+                #imports
+                #body
+            }
+        }
+    }
+}
+
+pub fn derive_from_pike(input: syn::DeriveInput) -> quote::Tokens {
+    let ident = input.ident;
+    let desc = ident.to_string();
+    let body = match input.data {
+        syn::Data::Struct(ref data) => {
+            let infos = field_infos(&data.fields, |_, field_ident| {
+                quote! { #field_ident }
+            });
+            fields_from_pike(&data.fields, &infos, quote! { #ident }, &desc)
+        },
+        syn::Data::Enum(ref data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let (name, infos) = variant_infos(variant);
+                let variant_ident = variant.ident;
+                let path = quote! { #ident::#variant_ident };
+                if let syn::Fields::Unit = variant.fields {
+                    return quote! { (#name, _) => Ok(#path) };
+                }
+                let variant_desc = format!("{}::{}", desc, variant_ident);
+                let conversion = fields_from_pike(&variant.fields, &infos,
+                    path, &variant_desc);
+                quote! {
+                    (#name, Some(thing)) => {
+                        let res: Result<Self, ConversionError> =
+                            (move || #conversion)();
+                        res.map_err(|e| e.at_key(#name))
+                    },
+                    (#name, None) => Err(ConversionError::new(format!(
+                        "Variant {} of {} has no content.", #name, #desc)))
+                }
+            });
+            quote! {
+                let (variant, content) = match thing {
+                    PikeThing::PikeString(s_ref) => {
+                        (String::from_pike(PikeThing::PikeString(s_ref), ctx)?, None)
+                    },
+                    PikeThing::Mapping(m_ref) => {
+                        let m: PikeMapping = m_ref.into_with_ctx(ctx);
+                        if m.len() != 1 {
+                            return Err(ConversionError::new(format!(
+                                "Expected a mapping with a single variant of {}.",
+                                #desc)));
+                        }
+                        match m.into_iter().next() {
+                            Some((key, content)) => {
+                                let variant = String::from_pike(key, ctx)
+                                    .map_err(|_| ConversionError::new(format!(
+                                        "Expected a string variant name of {}.",
+                                        #desc)))?;
+                                (variant, Some(content))
+                            },
+                            None => unreachable!()
+                        }
+                    },
+                    ref other => return Err(ConversionError::new(format!(
+                        "Expected enum {}, got {}.", #desc, other.type_name())))
+                };
+                match (variant.as_str(), content) {
+                    #(#arms,)*
+                    (other, _) => Err(ConversionError::new(format!(
+                        "Unknown variant {} of {}.", other, #desc)))
+                }
+            }
+        },
+        syn::Data::Union(_) => panic!("Unions can't derive FromPike")
+    };
+
+    let generics = add_trait_bounds(input.generics.clone(),
+        "::pike_bindings::traits::FromPike");
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let imports = imports();
+    quote! {
+        impl #impl_generics ::pike_bindings::traits::FromPike
+        for #ident #ty_generics #where_clause {
+            fn from_pike(thing: ::pike_bindings::types::PikeThing,
+                ctx: &::pike_bindings::interpreter::PikeContext)
+            -> Result<Self, ::pike_bindings::serialization::Error> {
+                #imports
+                #body
+            }
+        }
+    }
+}
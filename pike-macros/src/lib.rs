@@ -10,6 +10,8 @@ extern crate serde_derive;
 
 use proc_macro::TokenStream;
 
+mod derive;
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 enum TypeMetadata {
     Int,
@@ -34,9 +36,10 @@ struct ExportMetadata {
 }
 
 enum ExportType {
-    SelfRef,
+    SelfRef(bool),
+    Context,
     Unit,
-    Int,
+    Int(syn::Type),
     Float,
     StrRef,
     String,
@@ -44,16 +47,31 @@ enum ExportType {
     PikeFunction,
     PikeThing,
     PikeString,
+    PikeArray,
+    PikeMapping,
+    PikeMultiset,
     Result(Box<ExportType>),
+    Option(Box<ExportType>),
+    Bool,
+    // Collections are converted with ToPike and FromPike, so they carry
+    // their Rust types as well as the types of their elements.
+    Vec(syn::Type, Box<ExportType>),
+    Map(syn::Type, Box<ExportType>, Box<ExportType>),
+    Set(syn::Type, Box<ExportType>),
+    Tuple(syn::Type, Vec<ExportType>),
     Wrapped(syn::Type),
-    WrappedRef(syn::Type),
+    WrappedRef(syn::Type, bool),
+    // Types that are converted with ToPike and FromPike, see
+    // `#[pike_convert]`.
+    Converted(syn::Type),
     Slice(syn::Type),
     Unknown(syn::Type)
 }
 
 struct ExportArg {
     ident: syn::Ident,
-    ty: ExportType
+    ty: ExportType,
+    rust_ty: Option<syn::Type>
 }
 
 struct Export {
@@ -61,7 +79,9 @@ struct Export {
     return_ty: ExportType,
     args: Vec<ExportArg>,
     is_constructor: bool,
-    struct_ty: Option<syn::Ident>
+    struct_ty: Option<syn::Ident>,
+    defaults: Vec<(syn::Ident, syn::Expr)>,
+    rest: Option<syn::Ident>
 }
 
 fn match_shallow_path( path: &syn::Path ) -> Option< &str > {
@@ -83,26 +103,45 @@ fn match_shallow_path( path: &syn::Path ) -> Option< &str > {
                 _ => None
             }
         }
-        _ => { None }
+        // Handle types like PikeArray<'a>, which are bound to the context.
+        syn::PathArguments::AngleBracketed(ref abga) => {
+            let only_lifetimes = abga.args.iter().all(|arg| {
+                matches!(arg, syn::GenericArgument::Lifetime(_))
+            });
+            if only_lifetimes { Some(name) } else { None }
+        }
     }
 }
 
-fn match_result_type(ty: &syn::Type) -> Option<ExportType> {
+// Matches the generic types Result<T, E>, Option<T> and the collections.
+fn match_generic_type(ty: &syn::Type) -> Option<ExportType> {
     if let syn::Type::Path(type_path) = ty {
         if let Some(last_seg) = &type_path.path.segments.last() {
             let seg = last_seg.value();
-            if seg.ident == "Result" {
-                if let syn::PathArguments::AngleBracketed(abga) = &seg.arguments {
-                    if let Some(first_arg) = abga.args.first() {
-                        if let syn::GenericArgument::Type(gty) = first_arg.into_value() {
-                            let inner_type = match_type (&gty);
-                            let res = ExportType::Result(
-                                Box::new(inner_type));
-                            return Some(res);
-                        }
-                    }
-                }
-            }
+            let args: Vec<ExportType> = match &seg.arguments {
+                syn::PathArguments::AngleBracketed(abga) => {
+                    abga.args.iter().filter_map(|arg| match arg {
+                        syn::GenericArgument::Type(gty) => Some(match_type(gty)),
+                        _ => None
+                    }).collect()
+                },
+                _ => return None
+            };
+            let mut args = args.into_iter().map(Box::new);
+            let res = match (seg.ident.as_ref(), args.len()) {
+                ("Result", n) if n > 0 => ExportType::Result(args.next().unwrap()),
+                ("Option", 1) => ExportType::Option(args.next().unwrap()),
+                ("Vec", 1) => ExportType::Vec(ty.clone(), args.next().unwrap()),
+                ("HashMap", 2) | ("BTreeMap", 2) => {
+                    let key = args.next().unwrap();
+                    ExportType::Map(ty.clone(), key, args.next().unwrap())
+                },
+                ("HashSet", 1) | ("BTreeSet", 1) => {
+                    ExportType::Set(ty.clone(), args.next().unwrap())
+                },
+                _ => return None
+            };
+            return Some(res);
         }
     }
     None
@@ -122,23 +161,33 @@ fn ident_from_type(ty: &syn::Type) -> Option<syn::Ident> {
 fn match_type( ty: &syn::Type ) -> ExportType {
     match ty {
         &syn::Type::Reference( ref ty ) => {
-            assert!( ty.mutability.is_none(), "`mut` bindings are not supported" );
-            match *ty.elem {
+            let mutable = ty.mutability.is_some();
+            let export_type = match *ty.elem {
                 syn::Type::Path( ref path ) => {
-                    if match_shallow_path(&path.path).map( |path| path == "str" ).unwrap_or( false ) {
-                        ExportType::StrRef
-                    } else {
-                        match_type(&*ty.elem)
+                    match match_shallow_path(&path.path) {
+                        Some("str") => ExportType::StrRef,
+                        Some("PikeContext") => ExportType::Context,
+                        // References to exported classes borrow the storage
+                        // of the Pike object.
+                        _ => match match_type(&*ty.elem) {
+                            ExportType::Wrapped(t) => {
+                                return ExportType::WrappedRef(t, mutable)
+                            },
+                            _ => panic!("Only `&str`, slices, `&PikeContext` and \
+                                exported classes may be taken by reference")
+                        }
                     }
                 },
                 syn::Type::Slice( ref slice ) => {
                     ExportType::Slice( (*slice.elem).clone() )
                 },
-                ref elem => ExportType::WrappedRef( elem.clone() )
-            }
+                ref elem => ExportType::WrappedRef( elem.clone(), mutable )
+            };
+            assert!( !mutable, "`&mut` is only supported for exported classes" );
+            export_type
         },
         &syn::Type::Path( ref path ) => {
-            if let Some(export_type) = match_result_type(&ty) {
+            if let Some(export_type) = match_generic_type(&ty) {
                 return export_type;
             }
 
@@ -148,23 +197,26 @@ fn match_type( ty: &syn::Type ) -> ExportType {
             };
 
             match name {
-                "i64" => ExportType::Int,
-                "u64" => ExportType::Int,
-                "i32" => ExportType::Int,
-                "u32" => ExportType::Int,
-                "i16" => ExportType::Int,
-                "u16" => ExportType::Int,
-                "i8" => ExportType::Int,
-                "u8" => ExportType::Int,
+                "i128" | "u128" |
+                "i64" | "u64" |
+                "i32" | "u32" |
+                "i16" | "u16" |
+                "i8" | "u8" |
+                "isize" | "usize" => ExportType::Int( ty.clone() ),
 
                 "f64" => ExportType::Float,
                 "f32" => ExportType::Float,
 
+                "bool" => ExportType::Bool,
+
                 "String" => ExportType::String,
                 "Fn" => ExportType::Fn,
                 "PikeFunction" => ExportType::PikeFunction,
                 "PikeThing" => ExportType::PikeThing,
                 "PikeString" => ExportType::PikeString,
+                "PikeArray" => ExportType::PikeArray,
+                "PikeMapping" => ExportType::PikeMapping,
+                "PikeMultiset" => ExportType::PikeMultiset,
                 _ => ExportType::Wrapped( ty.clone() )
             }
         },
@@ -172,7 +224,8 @@ fn match_type( ty: &syn::Type ) -> ExportType {
             if tuple.elems.is_empty() {
                 return ExportType::Unit
             }
-            ExportType::Unknown(ty.clone())
+            let elems = tuple.elems.iter().map(match_type).collect();
+            ExportType::Tuple(ty.clone(), elems)
         },
         &syn::Type::Paren(ref tp) => {
             match_type(&*tp.elem)
@@ -181,8 +234,50 @@ fn match_type( ty: &syn::Type ) -> ExportType {
     }
 }
 
+// Replaces the lifetimes in a type with '_, so that it can be used in the
+// wrapper function, where the lifetimes of the exported function aren't
+// declared. They are then inferred from the wrapper's context.
+fn erase_lifetimes(ty: &syn::Type) -> syn::Type {
+    let mut ty = ty.clone();
+    match ty {
+        syn::Type::Reference(ref mut r) => {
+            r.lifetime = None;
+            *r.elem = erase_lifetimes(&r.elem);
+        },
+        syn::Type::Path(ref mut p) => {
+            for seg in p.path.segments.iter_mut() {
+                if let syn::PathArguments::AngleBracketed(ref mut abga) = seg.arguments {
+                    for arg in abga.args.iter_mut() {
+                        match *arg {
+                            syn::GenericArgument::Lifetime(ref mut l) => {
+                                *l = syn::Lifetime::new("'_", l.span());
+                            },
+                            syn::GenericArgument::Type(ref mut t) => {
+                                *t = erase_lifetimes(t);
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        },
+        syn::Type::Slice(ref mut s) => {
+            *s.elem = erase_lifetimes(&s.elem);
+        },
+        syn::Type::Tuple(ref mut t) => {
+            for elem in t.elems.iter_mut() {
+                *elem = erase_lifetimes(elem);
+            }
+        },
+        syn::Type::Paren(ref mut p) => {
+            *p.elem = erase_lifetimes(&p.elem);
+        },
+        _ => {}
+    }
+    ty
+}
+
 fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
-    assert!( decl.generics.lifetimes().next().is_none(), "Lifetimes are not yet not supported" );
     assert!( decl.generics.type_params().next().is_none(), "Generics are not supported" );
     assert!( decl.generics.where_clause.is_none(), "`where` clauses are not supported" );
     assert!( decl.variadic.is_none(), "Variadic functions are not supported" );
@@ -195,11 +290,12 @@ fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
     let mut args = Vec::new();
     for (index, arg) in decl.inputs.iter().cloned().enumerate() {
         match arg {
-            syn::FnArg::SelfRef(_ty) => {
+            syn::FnArg::SelfRef(self_ref) => {
                 let ident = syn::Ident::from("__self");
                 args.push (ExportArg {
                     ident,
-                    ty: ExportType::SelfRef
+                    ty: ExportType::SelfRef(self_ref.mutability.is_some()),
+                    rust_ty: None
                 });
             },
             syn::FnArg::SelfValue( .. ) => panic!( "`self` is not supported" ),
@@ -207,7 +303,8 @@ fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
                 let ident = syn::Ident::from( format!( "__arg_{}", index ) );
                 args.push( ExportArg {
                     ident,
-                    ty: match_type( &ty )
+                    ty: match_type( &ty ),
+                    rust_ty: Some( erase_lifetimes( &ty ) )
                 });
             },
             syn::FnArg::Captured( cap ) => {
@@ -216,7 +313,8 @@ fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
                         let ident = syn::Ident::from( format!( "__arg_{}", index ) );
                         args.push( ExportArg {
                             ident,
-                            ty: match_type( &cap.ty )
+                            ty: match_type( &cap.ty ),
+                            rust_ty: Some( erase_lifetimes( &cap.ty ) )
                         });
                     },
                     syn::Pat::Ident( pat ) => {
@@ -226,7 +324,8 @@ fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
 
                         args.push( ExportArg {
                             ident: pat.ident,
-                            ty: match_type( &cap.ty )
+                            ty: match_type( &cap.ty ),
+                            rust_ty: Some( erase_lifetimes( &cap.ty ) )
                         });
                     },
                     _ => panic!( "Argument patterns are not supported" )
@@ -241,46 +340,66 @@ fn into_export( ident: syn::Ident, decl: &syn::FnDecl ) -> Export {
         return_ty,
         args,
         is_constructor: false,
-        struct_ty: None
+        struct_ty: None,
+        defaults: Vec::new(),
+        rest: None
     }
 }
 
-fn pike_return_type(return_ty: &ExportType) -> &str {
+fn pike_return_type(return_ty: &ExportType) -> String {
     match return_ty {
         ExportType::Unit => {
-            "void"
-        },
-        ExportType::Int => {
-            "int"
-        },
-        ExportType::Float => {
-            "float"
-        },
-        ExportType::StrRef => {
-            "string"
-        },
-        ExportType::String => {
-            "string"
+            "void".to_string()
         },
-        ExportType::Fn => {
-            "function"
+        ExportType::Wrapped(_t) => {
+            "object".to_string()
         },
-        ExportType::PikeFunction => {
-            "function"
+        ExportType::Result(t) => {
+            pike_return_type(&*t)
         },
-        ExportType::PikeThing => {
-            "mixed"
+        ExportType::Option(t) => {
+            format!("{}|zero", pike_return_type(t))
         },
-        ExportType::PikeString => {
-            "string"
+        _ => pike_type(return_ty)
+    }
+}
+
+// Returns the Pike type of a value, also when it is an element of a
+// collection.
+fn pike_type(ty: &ExportType) -> String {
+    match ty {
+        ExportType::Int(_t) => "int".to_string(),
+        ExportType::Bool => "int(0..1)".to_string(),
+        ExportType::Float => "float".to_string(),
+        ExportType::StrRef | ExportType::String | ExportType::PikeString => {
+            "string".to_string()
         },
-        ExportType::Wrapped(_t) => {
-            "object"
+        ExportType::Fn | ExportType::PikeFunction => "function".to_string(),
+        ExportType::PikeArray => "array".to_string(),
+        ExportType::PikeMapping => "mapping".to_string(),
+        ExportType::PikeMultiset => "multiset".to_string(),
+        ExportType::Option(t) => format!("{}|zero", pike_type(t)),
+        ExportType::Vec(_, t) => format!("array({})", pike_type(t)),
+        ExportType::Slice(t) => format!("array({})", pike_type(&match_type(t))),
+        ExportType::Map(_, k, v) => {
+            format!("mapping({}:{})", pike_type(k), pike_type(v))
         },
-        ExportType::Result(t) => {
-            pike_return_type(&*t)
+        ExportType::Set(_, t) => format!("multiset({})", pike_type(t)),
+        ExportType::Tuple(_, ts) => {
+            let mut types: Vec<String> = Vec::new();
+            for t in ts.iter().map(pike_type) {
+                if !types.contains(&t) {
+                    types.push(t);
+                }
+            }
+            format!("array({})", types.join("|"))
         },
-        _ => { panic!("Unhandled return type"); }
+        // Types that are converted with ToPike, i.e. with `#[pike_convert]`
+        // or as elements of collections, can be anything.
+        ExportType::PikeThing | ExportType::Wrapped(_) |
+        ExportType::Converted(_) => "mixed".to_string(),
+        ExportType::WrappedRef(..) => "object".to_string(),
+        _ => { panic!("Unhandled type"); }
     }
 }
 
@@ -305,6 +424,12 @@ fn result_wrapper_code(ty: &ExportType, export: &Export, call: quote::Tokens)
             }
         },
 
+        // Goes through TryFromWithCtx, since the wider int types may need to
+        // be converted to bignums.
+        ExportType::Int(_) => {
+            quote! { PikeThing::try_from_with_ctx(#call, &ctx) }
+        },
+
         ExportType::Result(ref inner_ty) => {
             let inner_call = quote! {
                 match #call {
@@ -320,6 +445,28 @@ fn result_wrapper_code(ty: &ExportType, export: &Export, call: quote::Tokens)
             result_wrapper_code(inner_ty, export, inner_call)
         },
 
+        // None is returned as UNDEFINED.
+        ExportType::Option(ref inner_ty) => {
+            let inner_conversion =
+                result_wrapper_code(inner_ty, export, quote! { val });
+            quote! {
+                match #call {
+                    Some(val) => {
+                        #inner_conversion
+                    },
+                    None => {
+                        Ok(PikeThing::undefined())
+                    }
+                }
+            }
+        },
+
+        ExportType::Bool | ExportType::StrRef | ExportType::String |
+        ExportType::Vec(..) | ExportType::Slice(_) | ExportType::Map(..) |
+        ExportType::Set(..) | ExportType::Tuple(..) | ExportType::Converted(_) => {
+            quote! { Ok(ToPike::to_pike(&#call, &ctx)?) }
+        },
+
         _ => {
             quote! { Ok(#call.into()) }
         }
@@ -454,6 +601,230 @@ fn gen_create_wrapper_func(export_ident: &syn::Ident,
       }
 }
 
+// Returns the ident of the temporary variable used for converting an argument.
+fn tmp_arg_ident(ident: &syn::Ident) -> syn::Ident {
+    syn::Ident::new(&format!("{}_tmp", ident), ident.span())
+}
+
+// Returns how an argument is referred to in error messages.
+fn arg_display_name(ident: &syn::Ident) -> String {
+    if ident == "__self" {
+        "the current object".to_string()
+    } else {
+        format!("argument {}", ident)
+    }
+}
+
+// Returns the Pike type of an argument, and code that converts the PikeThing
+// given by `thing` to it and binds the result to ident.
+fn arg_conversion(ty: &ExportType, ident: &syn::Ident, thing: quote::Tokens)
+    -> (String, quote::Tokens) {
+    let tmp_arg_ident = tmp_arg_ident(ident);
+
+    match ty {
+        ExportType::Int(ref t) => {
+            // Bignums are accepted too, with a range check against the Rust
+            // type.
+            ("int".to_string(), quote! {
+                let #ident: #t = <#t as TryFromWithCtx<PikeThing>>
+                    ::try_from_with_ctx(#thing, &ctx)?;
+            })
+        },
+        ExportType::Float => {
+            ("float".to_string(), quote! {
+                let #ident: PikeFloat = match #thing {
+                    PikeThing::Float(res) => { res }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected float.".to_string())); }
+                };
+            })
+        },
+        // Strings with invalid code points are rejected.
+        ExportType::String => {
+            ("string".to_string(), quote! {
+                let #ident: String = match #thing {
+                    PikeThing::PikeString(res) => {
+                        <String as ::std::convert::TryFrom<PikeString>>
+                            ::try_from(PikeString::from_with_ctx(res, &ctx))?
+                    }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
+                };
+            })
+        },
+        ExportType::StrRef => {
+            ("string".to_string(), quote! {
+                let #tmp_arg_ident: String = match #thing {
+                    PikeThing::PikeString(res) => {
+                        <String as ::std::convert::TryFrom<PikeString>>
+                            ::try_from(PikeString::from_with_ctx(res, &ctx))?
+                    }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
+                };
+                let #ident: &str = &#tmp_arg_ident;
+            })
+        },
+        ExportType::Fn | ExportType::PikeFunction => {
+            ("function".to_string(), quote! {
+                let #ident = match #thing {
+                    PikeThing::Function(res) => { PikeFunction::from_with_ctx(res, &ctx) }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected function.".to_string())); }
+                };
+            })
+        },
+        ExportType::PikeThing => {
+            ("mixed".to_string(), quote! {
+                let #ident = #thing;
+            })
+        },
+        ExportType::PikeString => {
+            ("string".to_string(), quote! {
+                let #ident = match #thing {
+                    PikeThing::PikeString(res) => { PikeString::from_with_ctx(res, &ctx) }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
+                };
+            })
+        },
+        // The handle types are bound to the wrapper's context.
+        ExportType::PikeArray => {
+            (pike_type(ty), quote! {
+                let #ident = match #thing {
+                    PikeThing::Array(res) => { PikeArray::from_with_ctx(res, &ctx) }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected array.".to_string())); }
+                };
+            })
+        },
+        ExportType::PikeMapping => {
+            (pike_type(ty), quote! {
+                let #ident = match #thing {
+                    PikeThing::Mapping(res) => { PikeMapping::from_with_ctx(res, &ctx) }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected mapping.".to_string())); }
+                };
+            })
+        },
+        ExportType::PikeMultiset => {
+            (pike_type(ty), quote! {
+                let #ident = match #thing {
+                    PikeThing::Multiset(res) => { PikeMultiset::from_with_ctx(res, &ctx) }
+                    _ => { return Err(PikeError::Args("Wrong argument type, expected multiset.".to_string())); }
+                };
+            })
+        },
+        // The object must be an instance of the class' program, or of a
+        // program that inherits it, see PikeObject::downcast(). The Pike type
+        // is filled in at runtime, see object_type_code().
+        // The storage is borrowed by process(), see object_borrows.
+        ExportType::WrappedRef(ref t, ref mutable) => {
+            let class_ident = ident_from_type(t).expect("Got no ident from type");
+            assert!(class_ident != "Self", "Use the class name instead of `Self`");
+            let errmsg = format!("Wrong argument type, expected {}.", class_ident);
+            let mutability = if *mutable { quote! { mut } } else { quote! {} };
+            ("{}".to_string(), quote! {
+                let #mutability #tmp_arg_ident = match #thing {
+                    PikeThing::Object(res) => {
+                        PikeObject::<()>::from_with_ctx(res, &ctx).downcast::<#t>()
+                    }
+                    _ => None
+                }.ok_or_else(|| PikeError::Args(#errmsg.to_string()))?;
+            })
+        },
+        // Collections are converted with FromPike.
+        ExportType::Bool | ExportType::Vec(..) | ExportType::Map(..) |
+        ExportType::Set(..) | ExportType::Tuple(..) | ExportType::Converted(_) => {
+            let rust_ty = match ty {
+                ExportType::Vec(t, _) | ExportType::Map(t, _, _) |
+                ExportType::Set(t, _) | ExportType::Tuple(t, _) |
+                ExportType::Converted(t) => quote! { #t },
+                _ => quote! { bool }
+            };
+            (pike_type(ty), quote! {
+                let #ident: #rust_ty = FromPike::from_pike(#thing, &ctx)
+                    .map_err(|e| PikeError::Args(format!("Wrong argument type: {}", e)))?;
+            })
+        },
+        ExportType::Slice(ref elem_ty) => {
+            (pike_type(ty), quote! {
+                let #tmp_arg_ident: Vec<#elem_ty> = FromPike::from_pike(#thing, &ctx)
+                    .map_err(|e| PikeError::Args(format!("Wrong argument type: {}", e)))?;
+                let #ident: &[#elem_ty] = &#tmp_arg_ident;
+            })
+        },
+        ExportType::Option(_) => {
+            panic!("Nested optional arguments are not supported");
+        },
+        ExportType::Wrapped(ref t) => {
+            let name = ident_from_type(t).map(|i| i.to_string()).unwrap_or_default();
+            panic!("Exported classes are passed by reference, e.g. `&{}`. Use \
+                `#[pike_convert({})]` for types that implement FromPike.",
+                name, ident);
+        },
+        _ => { panic!("Unhandled argument type"); }
+    }
+}
+
+// Returns code that evaluates to the Pike type of an object argument of the
+// class ty, i.e. object(is <program id>). The program of the class that is
+// being compiled is referred to as this_program. Other classes must have been
+// exported before, otherwise the type is just object.
+fn object_type_code(ty: &syn::Type, export: &Export) -> quote::Tokens {
+    let class_ident = ident_from_type(ty).expect("Got no ident from type");
+    if export.struct_ty == Some(class_ident) {
+        return quote! { "object(is this_program)" };
+    }
+    let program_var = program_var_name(&class_ident);
+    quote! {
+        unsafe {
+            match #program_var.as_ref() {
+                Some(prog_ref) => format!("object(is {})", prog_ref.id()),
+                None => "object".to_string()
+            }
+        }
+    }
+}
+
+// Returns the Pike type of a rest argument, and code that collects the
+// arguments from arg_idx onwards into it. The argument can be a Vec of any
+// argument type, or &[PikeThing].
+fn rest_arg_conversion(arg: &ExportArg, ident: &syn::Ident, arg_idx: i32)
+    -> (String, quote::Tokens) {
+    let elem_ident = syn::Ident::from(format!("{}_elem", ident));
+    let thing = quote! { ctx.get_from_stack((-args + __idx) as isize) };
+    let rust_ty = &arg.rust_ty;
+
+    if let ExportType::Slice(ref elem_ty) = arg.ty {
+        if let ExportType::PikeThing = match_type(elem_ty) {
+            let tmp_ident = syn::Ident::from(format!("{}_tmp", ident));
+            return ("mixed ...".to_string(), quote! {
+                let #tmp_ident: Vec<PikeThing> = (#arg_idx..args)
+                    .map(|__idx| #thing)
+                    .collect();
+                let #ident: #rust_ty = &#tmp_ident;
+            });
+        }
+    }
+
+    let elem_ty = match arg.ty {
+        ExportType::Vec(_, ref elem_ty) => elem_ty,
+        _ => panic!("`#[pike_rest]` arguments must be `Vec<T>` or `&[PikeThing]`")
+    };
+    match **elem_ty {
+        ExportType::StrRef => {
+            panic!("`&str` rest arguments are not supported, use `Vec<String>`");
+        },
+        ExportType::Slice(_) => {
+            panic!("Slice rest arguments are not supported, use `Vec<Vec<T>>`");
+        },
+        _ => {}
+    }
+    let (pike_type, conversion) =
+        arg_conversion(elem_ty, &elem_ident, thing);
+    (format!("{} ...", pike_type), quote! {
+        let mut #ident: #rust_ty = Vec::with_capacity((args - #arg_idx).max(0) as usize);
+        for __idx in #arg_idx..args {
+            #conversion
+            #ident.push(#elem_ident.into());
+        }
+    })
+}
+
 fn process( exports: Vec< Export > ) -> quote::Tokens {
     let mut output = Vec::new();
 
@@ -462,139 +833,217 @@ fn process( exports: Vec< Export > ) -> quote::Tokens {
         let mut export_args_conversions = Vec::new();
 
         let mut pike_args_types = Vec::new();
+        let mut object_types = Vec::new();
+        // The Pike objects whose storage is borrowed, as (object, argument,
+        // reference type, mutable).
+        let mut object_borrows = Vec::new();
         let mut self_ref = false;
 
         let mut num_args = 0i32;
+        let mut min_args = 0i32;
+        let mut optional_found = false;
+        let mut has_rest = false;
         let mut arg_idx_offset = 0i32;
 
+        if let Some(rest) = export.rest {
+            assert!(export.args.iter().any(|arg| arg.ident == rest),
+                "`#[pike_rest]` names an unknown argument `{}`", rest);
+        }
+        for (name, _) in &export.defaults {
+            assert!(export.args.iter().any(|arg| arg.ident == *name),
+                "`#[pike_default]` names an unknown argument `{}`", name);
+        }
+
         for (index, arg) in export.args.iter().enumerate() {
             let export_arg_ident = arg.ident.clone();
-            let mut tmp_arg_name = export_arg_ident.to_string();
-            tmp_arg_name.push_str("_tmp");
-            let tmp_arg_ident = syn::Ident::new(&tmp_arg_name,
-            export_arg_ident.span());
             let arg_idx = (index as i32) - arg_idx_offset;
-            let mut add_arg = true;
-
-            match arg.ty {
-                ExportType::SelfRef => {
-                    let struct_type = match export.struct_ty {
-                        Some(t) => {
-                            self_ref = true;
-                            t
-                        },
-                        None => { panic!("Self ref on non-struct method."); }
-                    };
-                    // FIXME: Check that current_object is an instance of the
-                    // expected Pike program.
-                    export_args_conversions.push(quote! {
-                        let mut cur_pike_obj = PikeObject::<#struct_type>::current_object(&ctx);
-                        let #export_arg_ident: &mut #struct_type = cur_pike_obj.wrapped();
-                    });
-                    add_arg = false;
-                    arg_idx_offset = 1;
-                },
-                ExportType::Int => {
-                    pike_args_types.push("int");
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident: PikeInt = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::Int(res) => { res }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected int.".to_string())); }
-                        };
-                    });
 
-                },
-                ExportType::Float => {
-                    pike_args_types.push("float");
-
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident: PikeFloat = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::Float(res) => { res }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected float.".to_string())); }
-                        };
-                    });
-                },
-                ExportType::String => {
-                    pike_args_types.push("string");
-
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident: PikeString = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::PikeString(res) => { PikeString::from_with_ctx(res, &ctx) }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
-                        };
-                    });
-                },
-                ExportType::StrRef => {
-                    pike_args_types.push("string");
-
-                    export_args_conversions.push(quote! {
-                        let #tmp_arg_ident: String = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::PikeString(res) => { PikeString::from_with_ctx(res, &ctx) }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
-                        };
-                        let #export_arg_ident: &str = &#tmp_arg_ident;
-                    });
-                },
-                ExportType::Fn => {
-                    pike_args_types.push("function");
-
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::Function(res) => { PikeFunction::from_with_ctx(res, &ctx)}
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected function.".to_string())); }
-                        };
-                    });
-                },
-                ExportType::PikeFunction => {
-                    pike_args_types.push("function");
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::Function(res) => { PikeFunction::from_with_ctx(res, &ctx) }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected function.".to_string())); }
-                        };
-                    });
-                },
-                ExportType::PikeThing => {
-                    pike_args_types.push("mixed");
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident = ctx.get_from_stack((-args + #arg_idx) as isize);
-                    });
-                },
-                ExportType::PikeString => {
-                    pike_args_types.push("string");
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::PikeString(res) => { PikeString::from_with_ctx(res, &ctx) }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
-                        };
-                    });
-                },
-                ExportType::WrappedRef(ref _t) => {
-                    pike_args_types.push("object");
-                    export_args_conversions.push(quote! {
-                        let #export_arg_ident = match ctx.get_from_stack((-args + #arg_idx) as isize) {
-                            PikeThing::PikeObject(res) => { PikeObject::from_with_ctx(res, &ctx) }
-                            _ => { return Err(PikeError::Args("Wrong argument type, expected string.".to_string())); }
-                        };
-                    });
-                },
-                _ => { panic!("Unhandled argument type"); }
+            if let ExportType::SelfRef(mutable) = arg.ty {
+                let struct_type = match export.struct_ty {
+                    Some(t) => {
+                        self_ref = true;
+                        t
+                    },
+                    None => { panic!("Self ref on non-struct method."); }
+                };
+                let errmsg = format!("Current object is not a {}.", struct_type);
+                let mutability = if mutable { quote! { mut } } else { quote! {} };
+                export_args_conversions.push(quote! {
+                    let #mutability cur_pike_obj = PikeObject::<()>::current_object(&ctx)
+                        .downcast::<#struct_type>()
+                        .ok_or_else(|| PikeError::Generic(#errmsg.to_string()))?;
+                });
+                let ref_ty = if mutable {
+                    quote! { &mut #struct_type }
+                } else {
+                    quote! { &#struct_type }
+                };
+                object_borrows.push((syn::Ident::from("cur_pike_obj"),
+                    export_arg_ident, ref_ty, mutable));
+                arg_idx_offset += 1;
+                continue;
+            }
+
+            // The context is passed from the wrapper, and isn't a Pike
+            // argument.
+            if let ExportType::Context = arg.ty {
+                export_args_idents.push( quote! { &ctx } );
+                arg_idx_offset += 1;
+                continue;
             }
-            if add_arg {
+
+            if export.rest == Some(export_arg_ident) {
+                assert!(index == export.args.len() - 1,
+                    "`#[pike_rest]` must name the last argument");
+                let (pike_type, conversion) =
+                    rest_arg_conversion(arg, &export_arg_ident, arg_idx);
+                pike_args_types.push(pike_type);
+                export_args_conversions.push(conversion);
                 export_args_idents.push( quote! { #export_arg_ident.into() } );
-                num_args += 1;
+                has_rest = true;
+                continue;
+            }
+
+            let default = export.defaults.iter()
+                .find(|d| d.0 == export_arg_ident)
+                .map(|d| d.1.clone());
+            let optional = match arg.ty {
+                ExportType::Option(_) => true,
+                _ => default.is_some()
+            };
+            if optional {
+                optional_found = true;
+            } else {
+                assert!(!optional_found, "Optional arguments must come last");
+                min_args += 1;
+            }
+
+            // Missing optional arguments are treated as UNDEFINED. Like in
+            // Pike, where a `string|void` argument is often passed as 0, an
+            // int 0 is treated as missing too unless ints are accepted.
+            let thing = if optional {
+                quote! {
+                    if args > #arg_idx {
+                        ctx.get_from_stack((-args + #arg_idx) as isize)
+                    } else {
+                        PikeThing::undefined()
+                    }
+                }
+            } else {
+                quote! { ctx.get_from_stack((-args + #arg_idx) as isize) }
+            };
+
+            let (value_ty, is_option) = match arg.ty {
+                ExportType::Option(ref inner_ty) => (&**inner_ty, true),
+                ref ty => (ty, false)
+            };
+            let (pike_type, conversion) = if optional {
+                match *value_ty {
+                    ExportType::StrRef => {
+                        panic!("Optional `&str` arguments are not supported, use `String`");
+                    },
+                    ExportType::Slice(_) => {
+                        panic!("Optional slice arguments are not supported, use `Vec`");
+                    },
+                    ExportType::WrappedRef(..) => {
+                        panic!("Optional object arguments are not supported");
+                    },
+                    _ => {}
+                }
+                let (pike_type, conversion) =
+                    arg_conversion(value_ty, &export_arg_ident, quote! { thing });
+                let missing = match default {
+                    Some(default) => quote! { #default },
+                    None => quote! { None }
+                };
+                let value = if is_option {
+                    quote! { Some(#export_arg_ident.into()) }
+                } else {
+                    quote! { #export_arg_ident.into() }
+                };
+                let zero = match *value_ty {
+                    ExportType::Int(_) | ExportType::Bool |
+                        ExportType::PikeThing | ExportType::Converted(_) |
+                        ExportType::Unknown(_) => quote! {},
+                    _ => quote! {
+                        PikeThing::Int(ref i) if i.integer == 0 => #missing,
+                    }
+                };
+                let rust_ty = &arg.rust_ty;
+                (format!("{}|void", pike_type), quote! {
+                    let #export_arg_ident: #rust_ty = match #thing {
+                        PikeThing::Undefined => #missing,
+                        #zero
+                        thing => {
+                            #conversion
+                            #value
+                        }
+                    };
+                })
+            } else {
+                arg_conversion(value_ty, &export_arg_ident, thing)
+            };
+            if let ExportType::WrappedRef(ref t, mutable) = arg.ty {
+                object_types.push(object_type_code(t, &export));
+                let ref_ty = if mutable {
+                    quote! { &mut #t }
+                } else {
+                    quote! { &#t }
+                };
+                object_borrows.push((tmp_arg_ident(&export_arg_ident),
+                    export_arg_ident, ref_ty, mutable));
             }
+            pike_args_types.push(pike_type);
+            export_args_conversions.push(conversion);
+            export_args_idents.push( quote! { #export_arg_ident.into() } );
+            num_args += 1;
         }
 
+        // The storage is borrowed once all objects are converted, so that
+        // they can be checked for overlaps first. The same object must not be
+        // borrowed twice when either borrow is mutable.
+        for (i, a) in object_borrows.iter().enumerate() {
+            for b in &object_borrows[i + 1..] {
+                if !a.3 && !b.3 {
+                    continue;
+                }
+                let (obj_a, obj_b) = (a.0, b.0);
+                let errmsg = format!("The same object was passed as {} and {}.",
+                    arg_display_name(&a.1), arg_display_name(&b.1));
+                export_args_conversions.push(quote! {
+                    if #obj_a.is_same_object(&#obj_b) {
+                        return Err(PikeError::Args(#errmsg.to_string()));
+                    }
+                });
+            }
+        }
+        // Shared borrows don't go through the mutable storage accessor, since
+        // the same object may be borrowed more than once.
+        for (obj, ident, ref_ty, mutable) in object_borrows {
+            let borrow = if mutable {
+                quote! { #obj.wrapped() }
+            } else {
+                quote! { #obj.wrapped_ref() }
+            };
+            export_args_conversions.push(quote! {
+                let #ident: #ref_ty = #borrow;
+            });
+        }
+
+        let max_args_check = if has_rest {
+            quote! {}
+        } else {
+            quote! { || args > #num_args }
+        };
         export_args_conversions.insert(0, quote! {
-            if args != #num_args {
+            if args < #min_args #max_args_check {
                 return Err(PikeError::Args("Wrong number of arguments".to_string()));
             }
         });
 
 
         if pike_args_types.len() == 0 {
-            pike_args_types.push("void");
+            pike_args_types.push("void".to_string());
         }
 
         let original_ident = export.ident.clone();
@@ -623,6 +1072,13 @@ fn process( exports: Vec< Export > ) -> quote::Tokens {
         let pike_func_type: String = format!("function({}:{})",
             pike_args_types.join(","),
             pike_return_type(&export.return_ty));
+        // Object types refer to the ids of programs, which are only known at
+        // runtime.
+        let pike_func_type = if object_types.is_empty() {
+            quote! { #pike_func_type }
+        } else {
+            quote! { &format!(#pike_func_type, #(#object_types),*) }
+        };
         EXPORT_FUNC_INITS.with(|e| {
             let ref mut a = *e.borrow_mut();
             a.push(
@@ -673,6 +1129,7 @@ fn handle_item_impl(mut orig_item: syn::Item) -> TokenStream {
             if let syn::ImplItem::Method(ref mut meth) = iimpl {
                 let name = meth.sig.ident.clone();
                 let mut export = into_export(name, &meth.sig.decl);
+                take_arg_attrs(&mut meth.attrs, &mut export);
                 export.struct_ty = struct_ty;
                 if export.ident.as_ref() == "create" {
                     export.is_constructor = true;
@@ -720,18 +1177,98 @@ fn handle_item_impl(mut orig_item: syn::Item) -> TokenStream {
     output.into()
 }
 
+// Makes an argument (or the return value, if ident is `return`) of a type
+// that isn't known to the macro, e.g. a struct that derives ToPike and
+// FromPike, be converted with those traits instead of as an exported class.
+fn convert(export: &mut Export, ident: syn::Ident) {
+    fn converted(ty: ExportType) -> ExportType {
+        match ty {
+            ExportType::Wrapped(t) => ExportType::Converted(t),
+            ExportType::Result(t) => ExportType::Result(Box::new(converted(*t))),
+            ExportType::Option(t) => ExportType::Option(Box::new(converted(*t))),
+            _ => panic!("`#[pike_convert]` only applies to types that are not \
+                otherwise supported")
+        }
+    }
+
+    if ident == "return" {
+        let ty = ::std::mem::replace(&mut export.return_ty, ExportType::Unit);
+        export.return_ty = converted(ty);
+        return;
+    }
+    let arg = export.args.iter_mut().find(|arg| arg.ident == ident)
+        .unwrap_or_else(|| {
+            panic!("`#[pike_convert]` names an unknown argument `{}`", ident)
+        });
+    let ty = ::std::mem::replace(&mut arg.ty, ExportType::Unit);
+    arg.ty = converted(ty);
+}
+
+// Removes the `#[pike_default(arg = "expr")]`, `#[pike_rest(arg)]` and
+// `#[pike_convert(arg, ..., return)]` attributes of a function and adds what
+// they specify to the export.
+fn take_arg_attrs(attrs: &mut Vec<syn::Attribute>, export: &mut Export) {
+    attrs.retain(|attr| {
+        let list = match attr.interpret_meta() {
+            Some(syn::Meta::List(list)) => list,
+            _ => return true
+        };
+        match list.ident.as_ref() {
+            "pike_default" => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => {
+                            let expr = match nv.lit {
+                                syn::Lit::Str(ref s) => syn::parse_str(&s.value())
+                                    .expect("Invalid expression in `#[pike_default]`"),
+                                _ => panic!("Default values must be strings, e.g. \
+                                    `#[pike_default(limit = \"10\")]`")
+                            };
+                            export.defaults.push((nv.ident, expr));
+                        },
+                        _ => panic!("Expected `#[pike_default(arg = \"expr\")]`")
+                    }
+                }
+            },
+            "pike_convert" => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        syn::NestedMeta::Meta(syn::Meta::Word(ident)) => {
+                            convert(export, *ident);
+                        },
+                        _ => panic!("Expected `#[pike_convert(arg, ...)]`")
+                    }
+                }
+            },
+            "pike_rest" => {
+                match list.nested.iter().next() {
+                    Some(syn::NestedMeta::Meta(syn::Meta::Word(ident)))
+                        if list.nested.len() == 1 => {
+                        export.rest = Some(*ident);
+                    },
+                    _ => panic!("Expected `#[pike_rest(arg)]`")
+                }
+            },
+            _ => return true
+        }
+        false
+    });
+}
+
 #[proc_macro_attribute]
 pub fn pike_export(attrs: TokenStream, input: TokenStream) -> TokenStream {
     let mut exports = Vec::new();
-    let item: syn::Item = syn::parse(input).unwrap();
+    let mut item: syn::Item = syn::parse(input).unwrap();
 
     if !attrs.is_empty() {
         panic!( "Extra attributes are not supported in `#[pike_export]`!" );
     }
 
     match item {
-        syn::Item::Fn(ref function) => {
-            exports.push(into_export(function.ident.clone(), &function.decl));
+        syn::Item::Fn(ref mut function) => {
+            let mut export = into_export(function.ident.clone(), &function.decl);
+            take_arg_attrs(&mut function.attrs, &mut export);
+            exports.push(export);
         },
         syn::Item::Impl(_) => {
             return handle_item_impl (item);
@@ -797,12 +1334,28 @@ pub fn init_pike_module(_input: TokenStream) -> TokenStream {
 
         #[no_mangle]
         pub extern "C" fn pike_module_init() {
+            let ctx = unsafe { PikeContext::assume_got_context() };
+            PikeFunction::init_closure_program(&ctx);
             #(#inits)*
         }
         #[no_mangle]
         pub extern "C" fn pike_module_exit() {
-
+            let ctx = unsafe { PikeContext::assume_got_context() };
+            PikeFunction::exit_closure_program(&ctx);
+            PikeProgram::<()>::clear_registry(&ctx);
         }
     };
     output.into()
-}
\ No newline at end of file
+}
+
+#[proc_macro_derive(ToPike, attributes(pike))]
+pub fn derive_to_pike(input: TokenStream) -> TokenStream {
+    let input: syn::DeriveInput = syn::parse(input).unwrap();
+    derive::derive_to_pike(input).into()
+}
+
+#[proc_macro_derive(FromPike, attributes(pike))]
+pub fn derive_from_pike(input: TokenStream) -> TokenStream {
+    let input: syn::DeriveInput = syn::parse(input).unwrap();
+    derive::derive_from_pike(input).into()
+}
@@ -0,0 +1,295 @@
+use ::serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer,
+    Visitor};
+use super::*;
+
+/// Deserializes a Rust value from data in the format of Pike's
+/// `encode_value()`.
+pub fn from_bytes<T>(data: &[u8]) -> Result<T, Error>
+where T: DeserializeOwned {
+    T::deserialize(Deserializer::new(decode(data)?))
+}
+
+/// A serde Deserializer that reads from a `Value`, using the same
+/// representations as `Serializer`.
+pub struct Deserializer {
+    value: Value
+}
+
+impl Deserializer {
+    pub fn new(value: Value) -> Self {
+        Deserializer { value }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::new(format!("Expected {}, got {}.", expected,
+            self.value.type_name()))
+    }
+
+    fn string(&self) -> Result<String, Error> {
+        match self.value.as_string() {
+            Some(s) => Ok(s),
+            None if self.value.type_name() == "string" => {
+                Err(Error::new("String contains invalid characters."))
+            },
+            None => Err(self.unexpected("a string"))
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.value {
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Bignum(ref b) => {
+                if b.magnitude.len() > 16 {
+                    return Err(Error::new("Integer is too large."));
+                }
+                let abs = b.magnitude.iter()
+                    .fold(0u128, |acc, &b| acc << 8 | u128::from(b));
+                if !b.negative && abs <= u128::from(u64::max_value()) {
+                    visitor.visit_u64(abs as u64)
+                } else if !b.negative {
+                    visitor.visit_u128(abs)
+                } else if abs <= 1 << 127 {
+                    visitor.visit_i128((abs as i128).wrapping_neg())
+                } else {
+                    Err(Error::new("Integer is too large."))
+                }
+            },
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(_) => visitor.visit_string(self.string()?),
+            Value::Array(values) | Value::Multiset(values) => {
+                visitor.visit_seq(SeqAccess::new(values))
+            },
+            Value::Mapping(entries) => {
+                visitor.visit_map(MapAccess::new(entries))
+            }
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.value {
+            Value::Int(i) => visitor.visit_bool(i != 0),
+            _ => Err(self.unexpected("a bool"))
+        }
+    }
+
+    // The format can't tell UNDEFINED from 0, so 0 is read as None.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.value {
+            Value::Int(0) => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.value {
+            Value::Int(0) => visitor.visit_unit(),
+            _ => Err(self.unexpected("0"))
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        if let Value::String(_) = self.value {
+            return match self.value.as_bytes() {
+                Some(bytes) => visitor.visit_byte_buf(bytes),
+                None => Err(Error::new("Expected an 8-bit string, got a wide string."))
+            };
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(self, name: &'static str,
+        _variants: &'static [&'static str], visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let (variant, content) = match self.value {
+            Value::String(_) => (self.string()?, None),
+            Value::Mapping(mut entries) => {
+                if entries.len() != 1 {
+                    return Err(Error::new(format!(
+                        "Expected a mapping with a single variant of {}.",
+                        name)));
+                }
+                let (key, content) = entries.remove(0);
+                match key.as_string() {
+                    Some(variant) => (variant, Some(content)),
+                    None => return Err(Error::new(format!(
+                        "Expected a string variant name of {}.", name)))
+                }
+            },
+            _ => return Err(Error::new(format!("Expected enum {}, got {}.",
+                name, self.value.type_name())))
+        };
+        visitor.visit_enum(Enum { variant, content })
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string seq
+        tuple tuple_struct map struct identifier
+    }
+}
+
+// Iterates the elements of an array or a multiset.
+struct SeqAccess {
+    iter: ::std::vec::IntoIter<Value>,
+    index: usize
+}
+
+impl SeqAccess {
+    fn new(values: Vec<Value>) -> Self {
+        SeqAccess { iter: values.into_iter(), index: 0 }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T)
+    -> Result<Option<T::Value>, Error>
+    where T: DeserializeSeed<'de> {
+        let value = match self.iter.next() {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(Deserializer::new(value))
+            .map(Some)
+            .map_err(|e| e.at_index(index))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+// Iterates the entries of a mapping.
+struct MapAccess {
+    iter: ::std::vec::IntoIter<(Value, Value)>,
+    value: Option<(String, Value)>
+}
+
+impl MapAccess {
+    fn new(entries: Vec<(Value, Value)>) -> Self {
+        MapAccess { iter: entries.into_iter(), value: None }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where K: DeserializeSeed<'de> {
+        let (key, value) = match self.iter.next() {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+        let key_desc = describe_key(&key);
+        let res = seed.deserialize(Deserializer::new(key))
+            .map_err(|e| e.at_key(key_desc.clone()))?;
+        self.value = Some((key_desc, value));
+        Ok(Some(res))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where V: DeserializeSeed<'de> {
+        let (key_desc, value) = self.value.take().ok_or_else(|| {
+            Error::new("next_value_seed called before next_key_seed")
+        })?;
+        seed.deserialize(Deserializer::new(value))
+            .map_err(|e| e.at_key(key_desc))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+// The variant name and content of an enum value.
+struct Enum {
+    variant: String,
+    content: Option<Value>
+}
+
+impl Enum {
+    fn content(self) -> Result<(String, Deserializer), Error> {
+        match self.content {
+            Some(content) => Ok((self.variant, Deserializer::new(content))),
+            None => Err(Error::new(format!("Variant {} has no content.",
+                self.variant)))
+        }
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where V: DeserializeSeed<'de> {
+        let variant: de::value::StrDeserializer<Error> =
+            self.variant.as_str().into_deserializer();
+        let res = seed.deserialize(variant)?;
+        Ok((res, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Enum {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where T: DeserializeSeed<'de> {
+        let (variant, de) = self.content()?;
+        seed.deserialize(de).map_err(|e| e.at_key(variant))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let (variant, de) = self.content()?;
+        de::Deserializer::deserialize_seq(de, visitor)
+            .map_err(|e| e.at_key(variant))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let (variant, de) = self.content()?;
+        de::Deserializer::deserialize_map(de, visitor)
+            .map_err(|e| e.at_key(variant))
+    }
+}
@@ -0,0 +1,307 @@
+use super::*;
+
+// The maximum nesting of containers. Deeper data is rejected instead of
+// overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Decodes data in the format of Pike's `encode_value()`.
+pub fn decode(data: &[u8]) -> Result<Value, Error> {
+    if !data.starts_with(HEADER) {
+        return Err(Error::new("Data is not in encode_value() format."));
+    }
+    let mut decoder = Decoder {
+        data,
+        pos: HEADER.len(),
+        entries: Vec::new(),
+        depth: 0
+    };
+    let value = decoder.value()?;
+    if decoder.pos < data.len() {
+        return Err(decoder.error("Unexpected data after value"));
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // The values decoded so far, for TAG_AGAIN, indexed by entry number minus
+    // COUNTER_START. Containers are None until they are completely decoded.
+    entries: Vec<Option<Value>>,
+    depth: usize
+}
+
+impl<'a> Decoder<'a> {
+    fn value(&mut self) -> Result<Value, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Data is nested too deeply"));
+        }
+        self.depth += 1;
+        let res = self.nested_value();
+        self.depth -= 1;
+        res
+    }
+
+    fn nested_value(&mut self) -> Result<Value, Error> {
+        let (tag, num) = self.entry()?;
+        if tag == TAG_AGAIN {
+            let index = num - COUNTER_START;
+            return match self.entries.get(index as usize) {
+                Some(Some(value)) if index >= 0 => Ok(value.clone()),
+                Some(None) if index >= 0 => {
+                    Err(self.error("Cyclic data is not supported"))
+                },
+                _ => Err(self.error(&format!("Unknown entry {}", num)))
+            };
+        }
+        let index = self.entries.len();
+        self.entries.push(None);
+
+        let value = match tag {
+            TAG_INT => Value::Int(num),
+            TAG_FLOAT => self.float(num)?,
+            TAG_STRING => Value::String(self.string(num)?),
+            TAG_ARRAY => {
+                let values: Result<Vec<_>, _> =
+                    (0..self.count(num)?).map(|_| self.value()).collect();
+                Value::Array(values?)
+            },
+            TAG_MAPPING => {
+                let entries: Result<Vec<_>, Error> = (0..self.count(num)?)
+                    .map(|_| Ok((self.value()?, self.value()?)))
+                    .collect();
+                Value::Mapping(entries?)
+            },
+            TAG_MULTISET => {
+                let values: Result<Vec<_>, _> =
+                    (0..self.count(num)?).map(|_| self.value()).collect();
+                Value::Multiset(values?)
+            },
+            TAG_OBJECT if num == OBJECT_BIGNUM => self.bignum()?,
+            TAG_OBJECT => {
+                return Err(self.error("Only bignum objects are supported"))
+            },
+            _ => return Err(self.error(&format!("Unsupported tag {}", tag)))
+        };
+        self.entries[index] = Some(value.clone());
+        Ok(value)
+    }
+
+    fn bignum(&mut self) -> Result<Value, Error> {
+        let digits = match self.value()? {
+            Value::String(ref chars) => {
+                let s: Option<String> = chars.iter()
+                    .map(|&c| ::std::char::from_u32(c as u32))
+                    .collect();
+                s.unwrap_or_default()
+            },
+            _ => return Err(self.error("Expected the digits of a bignum"))
+        };
+        match Bignum::from_base36(&digits) {
+            Some(b) => Ok(Value::from_int_bytes_be(b.negative, &b.magnitude)),
+            None => Err(self.error(&format!("Invalid bignum digits {:?}",
+                digits)))
+        }
+    }
+
+    // The value is x * 2^y, where x has already been read. If x is 0, y
+    // specifies a special value.
+    fn float(&mut self, x: i64) -> Result<Value, Error> {
+        let (_, y) = self.entry()?;
+        let f = if x != 0 {
+            // Split the scaling in two, in case 2^y itself is out of range.
+            let half = (y / 2) as i32;
+            x as f64 * 2f64.powi(half) * 2f64.powi(y as i32 - half)
+        } else {
+            match y {
+                FP_NZERO => -0.0,
+                FP_PINF => ::std::f64::INFINITY,
+                FP_NINF => ::std::f64::NEG_INFINITY,
+                FP_QNAN | FP_SNAN => ::std::f64::NAN,
+                _ => 0.0
+            }
+        };
+        Ok(Value::Float(f))
+    }
+
+    // A length of -1 means that a wide string follows.
+    fn string(&mut self, len: i64) -> Result<Vec<i32>, Error> {
+        let (shift, len) = if len == -1 {
+            let (shift, len) = self.entry()?;
+            if shift > 2 {
+                return Err(self.error(&format!("Invalid size shift {}", shift)));
+            }
+            (shift, len)
+        } else {
+            (0, len)
+        };
+        let len = self.count(len)?;
+        let width = 1 << shift;
+        let bytes = self.bytes(len.checked_mul(width)
+            .ok_or_else(|| self.error("String is too long"))?)?;
+        let chars = bytes.chunks(width).map(|c| {
+            match shift {
+                0 => i32::from(c[0]),
+                1 => i32::from(u16::from(c[0]) << 8 | u16::from(c[1])),
+                _ => i32::from_be_bytes([c[0], c[1], c[2], c[3]])
+            }
+        });
+        Ok(chars.collect())
+    }
+
+    // Reads a tag and a number, see Encoder::entry().
+    fn entry(&mut self) -> Result<(u8, i64), Error> {
+        let what = self.byte()?;
+        let size = what >> SIZE_SHIFT;
+        let mut num = if what & TAG_SMALL != 0 {
+            i64::from(size)
+        } else {
+            let mut num = 0;
+            for _ in 0..=size {
+                num = (num << 8) + i64::from(self.byte()?) + 1;
+            }
+            num + MAX_SMALL - 1
+        };
+        if what & TAG_NEG != 0 {
+            num = !num;
+        }
+        Ok((what & TAG_MASK, num))
+    }
+
+    // Checks a length read from the data. Every element takes at least one
+    // byte, so longer lengths must be invalid.
+    fn count(&self, num: i64) -> Result<usize, Error> {
+        if num < 0 || num as usize > self.data.len() - self.pos {
+            return Err(self.error(&format!("Invalid length {}", num)));
+        }
+        Ok(num as usize)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.data.len() - self.pos {
+            return Err(self.error("Unexpected end of data"));
+        }
+        let res = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(res)
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::new(format!("{} at byte {}.", msg, self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Arrays of one element, nested to the specified depth around a 0.
+    fn nested_arrays(depth: usize) -> Vec<u8> {
+        let mut data = HEADER.to_vec();
+        data.extend(vec![TAG_ARRAY | TAG_SMALL | 1 << SIZE_SHIFT; depth]);
+        data.push(TAG_INT | TAG_SMALL);
+        data
+    }
+
+    #[test]
+    fn decode_nested() {
+        assert!(decode(&nested_arrays(MAX_DEPTH - 1)).is_ok());
+    }
+
+    #[test]
+    fn decode_too_deeply_nested() {
+        assert!(decode(&nested_arrays(MAX_DEPTH)).is_err());
+        assert!(decode(&nested_arrays(1_000_000)).is_err());
+    }
+
+    // Checks that data (without the header) decodes to the expected value,
+    // and that the value encodes to the same data.
+    fn check(data: &[u8], expected: Value) {
+        let mut full = HEADER.to_vec();
+        full.extend_from_slice(data);
+        let decoded = decode(&full).unwrap();
+        assert!(decoded.same_as(&expected), "{:?} != {:?}", decoded, expected);
+        assert_eq!(encode(&expected), full);
+    }
+
+    fn string(s: &str) -> Value {
+        Value::from(s)
+    }
+
+    #[test]
+    fn decode_ints() {
+        check(&[0x28], Value::Int(0));
+        check(&[0x68], Value::Int(1));
+        check(&[0x38], Value::Int(-1));
+        check(&[0x08, 0x00], Value::Int(4));
+        check(&[0x48, 0x02, 0xe4], Value::Int(1000));
+        check(&[0x58, 0x02, 0xe3], Value::Int(-1000));
+        check(&[0xc8, 0x7e, 0xfe, 0xfe, 0xfb], Value::Int(0x7fff_ffff));
+    }
+
+    #[test]
+    fn decode_bignums() {
+        let mut data = vec![0xa3, 0x06, 0x03];
+        data.extend_from_slice(b"1z141z4");
+        check(&data, Value::Int(1 << 32));
+
+        let mut data = vec![0xa3, 0x06, 0x0a];
+        data.extend_from_slice(b"-3w5e11264sgsg");
+        check(&data, Value::Bignum(Bignum {
+            negative: true,
+            magnitude: vec![1, 0, 0, 0, 0, 0, 0, 0, 0]
+        }));
+    }
+
+    #[test]
+    fn decode_floats() {
+        check(&[0xc7, 0x2e, 0xfe, 0xfe, 0xfc, 0x17, 0x18], Value::Float(1.5));
+        check(&[0x27, 0x27], Value::Float(0.0));
+        check(&[0x27, 0x67], Value::Float(-0.0));
+        check(&[0x27, 0x37], Value::Float(::std::f64::INFINITY));
+        check(&[0x27, 0x77], Value::Float(::std::f64::NEG_INFINITY));
+        check(&[0x27, 0xb7], Value::Float(::std::f64::NAN));
+    }
+
+    #[test]
+    fn decode_strings() {
+        check(&[0xe6, b'f', b'o', b'o'], string("foo"));
+        check(&[0x36, 0x61, 0x20, 0xac], string("\u{20ac}"));
+        check(&[0x36, 0x62, 0x00, 0x01, 0xf6, 0x00], string("\u{1f600}"));
+    }
+
+    #[test]
+    fn decode_again() {
+        check(&[0xa0, 0xe6, b'f', b'o', b'o', 0xbf],
+            Value::Array(vec![string("foo"), string("foo")]));
+        // An array containing itself.
+        assert!(decode(&[HEADER, &[0xa0, 0x68, 0xff][..]].concat()).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        use ::std::collections::HashMap;
+        use super::super::{from_bytes, to_bytes};
+
+        let i: i64 = -(1 << 40);
+        assert_eq!(from_bytes::<i64>(&to_bytes(&i).unwrap()).unwrap(), i);
+
+        let s = "r\u{e4}ksm\u{f6}rg\u{e5}s \u{1f600}".to_string();
+        assert_eq!(from_bytes::<String>(&to_bytes(&s).unwrap()).unwrap(), s);
+
+        let v = vec![Some(1.5), None, Some(-0.25)];
+        let res: Vec<Option<f64>> = from_bytes(&to_bytes(&v).unwrap()).unwrap();
+        assert_eq!(res, v);
+
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), vec![1, 2, 3]);
+        m.insert("b".to_string(), vec![]);
+        let res: HashMap<String, Vec<i32>> =
+            from_bytes(&to_bytes(&m).unwrap()).unwrap();
+        assert_eq!(res, m);
+    }
+}
@@ -0,0 +1,177 @@
+use ::std::collections::HashMap;
+use super::*;
+
+/// Encodes a value in the format of Pike's `encode_value()`.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut encoder = Encoder {
+        out: HEADER.to_vec(),
+        counter: COUNTER_START,
+        seen: HashMap::new()
+    };
+    encoder.value(value);
+    encoder.out
+}
+
+// Values that are written once and then referred to with TAG_AGAIN, like
+// Pike does for equal ints, floats and strings.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    Float(u64),
+    String(Vec<i32>)
+}
+
+struct Encoder {
+    out: Vec<u8>,
+    counter: i64,
+    seen: HashMap<Key, i64>
+}
+
+impl Encoder {
+    fn value(&mut self, value: &Value) {
+        let key = match *value {
+            Value::Int(i) if fits_int(i) => Some(Key::Int(i)),
+            Value::Float(f) => Some(Key::Float(f.to_bits())),
+            Value::String(ref chars) => Some(Key::String(chars.clone())),
+            _ => None
+        };
+        if let Some(key) = key {
+            if let Some(&id) = self.seen.get(&key) {
+                self.entry(TAG_AGAIN, id);
+                return;
+            }
+            self.seen.insert(key, self.counter);
+        }
+        self.counter += 1;
+
+        match *value {
+            Value::Int(i) if fits_int(i) => self.entry(TAG_INT, i),
+            // Larger ints are written as bignums.
+            Value::Int(i) => {
+                let bytes = i.unsigned_abs().to_be_bytes();
+                let first_nonzero = bytes.iter().position(|&b| b != 0)
+                    .unwrap_or(bytes.len());
+                self.bignum(&Bignum {
+                    negative: i < 0,
+                    magnitude: bytes[first_nonzero..].to_vec()
+                })
+            },
+            Value::Bignum(ref b) => self.bignum(b),
+            Value::Float(f) => self.float(f),
+            Value::String(ref chars) => self.string(chars),
+            Value::Array(ref values) => {
+                self.entry(TAG_ARRAY, values.len() as i64);
+                for v in values {
+                    self.value(v);
+                }
+            },
+            Value::Mapping(ref entries) => {
+                self.entry(TAG_MAPPING, entries.len() as i64);
+                for (k, v) in entries {
+                    self.value(k);
+                    self.value(v);
+                }
+            },
+            Value::Multiset(ref values) => {
+                self.entry(TAG_MULTISET, values.len() as i64);
+                for v in values {
+                    self.value(v);
+                }
+            }
+        }
+    }
+
+    fn bignum(&mut self, bignum: &Bignum) {
+        self.entry(TAG_OBJECT, OBJECT_BIGNUM);
+        let digits = Value::from(bignum.to_base36());
+        self.value(&digits);
+    }
+
+    // Floats are written as two entries, x and y, where the value is x * 2^y.
+    fn float(&mut self, f: f64) {
+        let (x, y) = if f == 0.0 {
+            (0, if f.is_sign_negative() { FP_NZERO } else { FP_PZERO })
+        } else if f.is_nan() {
+            (0, FP_QNAN)
+        } else if f.is_infinite() {
+            (0, if f > 0.0 { FP_PINF } else { FP_NINF })
+        } else {
+            // Like Pike, scale the mantissa to 62 bits and keep the upper 30.
+            let (mantissa, exp) = frexp(f);
+            let x = (mantissa * (1u64 << 62) as f64) as i64;
+            (x >> 32, i64::from(exp) - 62 + 32)
+        };
+        self.entry(TAG_FLOAT, x);
+        self.entry(TAG_FLOAT, y);
+    }
+
+    // 8-bit strings are written as is. Wider strings are marked with a length
+    // of -1, followed by the size shift and length, and the characters in
+    // big-endian order.
+    fn string(&mut self, chars: &[i32]) {
+        let shift = if chars.iter().all(|c| (0..=0xff).contains(c)) {
+            0
+        } else if chars.iter().all(|c| (0..=0xffff).contains(c)) {
+            1
+        } else {
+            2
+        };
+        if shift == 0 {
+            self.entry(TAG_STRING, chars.len() as i64);
+            self.out.extend(chars.iter().map(|&c| c as u8));
+            return;
+        }
+        self.entry(TAG_STRING, -1);
+        self.entry(shift, chars.len() as i64);
+        for &c in chars {
+            if shift == 1 {
+                self.out.extend_from_slice(&(c as u16).to_be_bytes());
+            } else {
+                self.out.extend_from_slice(&c.to_be_bytes());
+            }
+        }
+    }
+
+    // Writes a tag and a number. Numbers below MAX_SMALL are stored in the
+    // tag byte, and larger ones in 1-4 following bytes.
+    fn entry(&mut self, tag: u8, num: i64) {
+        let (mut tag, mut num) = (tag, num);
+        if num < 0 {
+            tag |= TAG_NEG;
+            num = !num;
+        }
+        if num < MAX_SMALL {
+            self.out.push(tag | TAG_SMALL | (num as u8) << SIZE_SHIFT);
+            return;
+        }
+        num -= MAX_SMALL;
+        let mut size = 0;
+        while size < 3 && num >= 256 << (size * 8) {
+            num -= 256 << (size * 8);
+            size += 1;
+        }
+        self.out.push(tag | (size as u8) << SIZE_SHIFT);
+        for i in (0..=size).rev() {
+            self.out.push((num >> (i * 8)) as u8);
+        }
+    }
+}
+
+// Ints outside the 32-bit range are written as bignums.
+fn fits_int(i: i64) -> bool {
+    i >= i64::from(i32::min_value()) && i <= i64::from(i32::max_value())
+}
+
+// Splits a finite, nonzero float into a mantissa in [0.5, 1) (with the sign
+// of the float) and an exponent, like C's frexp().
+fn frexp(f: f64) -> (f64, i32) {
+    let bits = f.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32;
+    if exp == 0 {
+        // Subnormal, so scale it up first.
+        let (mantissa, exp) = frexp(f * (1u64 << 54) as f64);
+        return (mantissa, exp - 54);
+    }
+    let mantissa = f64::from_bits(bits & !(0x7ff << 52) | (1022 << 52));
+    (mantissa, exp - 1022)
+}
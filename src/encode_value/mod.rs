@@ -0,0 +1,98 @@
+//! Reading and writing the binary format of Pike's `encode_value()` without
+//! an interpreter.
+//!
+//! `encode()` and `decode()` convert between the format and a `Value` tree.
+//! `to_bytes()` and `from_bytes()` use it as a serde data format, so that any
+//! `Serialize`/`Deserialize` type can be exchanged with Pike services.
+//!
+//! Ints, bignums, floats, strings of all widths, arrays, mappings and
+//! multisets are supported. Other objects, programs, functions and types are
+//! not, and neither is data where a container contains itself.
+//!
+//! Note that the format can't tell `UNDEFINED` from 0, so `None` and `()` are
+//! written as 0, and 0 is read as `None` for an `Option`. Floats are stored
+//! with the same precision as Pike uses, about 30 bits of mantissa.
+
+mod de;
+mod decoder;
+mod encoder;
+mod ser;
+mod value;
+
+pub use self::de::{from_bytes, Deserializer};
+pub use self::decoder::decode;
+pub use self::encoder::encode;
+pub use self::ser::{to_bytes, Serializer};
+pub use self::value::{Value, Bignum};
+pub use ::serialization::Error;
+
+use ::interpreter::{PikeContext, PikeError};
+use ::types::*;
+
+const HEADER: &[u8] = b"\xb6ke0";
+
+const TAG_ARRAY: u8 = 0;
+const TAG_MAPPING: u8 = 1;
+const TAG_MULTISET: u8 = 2;
+const TAG_OBJECT: u8 = 3;
+const TAG_STRING: u8 = 6;
+const TAG_FLOAT: u8 = 7;
+const TAG_INT: u8 = 8;
+const TAG_AGAIN: u8 = 15;
+const TAG_MASK: u8 = 15;
+const TAG_NEG: u8 = 16;
+const TAG_SMALL: u8 = 32;
+const SIZE_SHIFT: u8 = 6;
+const MAX_SMALL: i64 = 1 << (8 - SIZE_SHIFT);
+
+// Entries are numbered from here, in the order they are encoded. TAG_AGAIN
+// refers back to an entry by its number.
+const COUNTER_START: i64 = -MAX_SMALL;
+
+// The object type of a bignum, which is followed by its digits in base 36.
+const OBJECT_BIGNUM: i64 = 2;
+
+// The exponents of special floats, whose mantissa is 0.
+const FP_PZERO: i64 = 0;
+const FP_NZERO: i64 = 1;
+const FP_PINF: i64 = -1;
+const FP_NINF: i64 = -2;
+const FP_QNAN: i64 = -3;
+const FP_SNAN: i64 = -4;
+
+// Returns a mapping key in a form suitable for error paths.
+fn describe_key(key: &Value) -> String {
+    match *key {
+        Value::Int(i) => i.to_string(),
+        _ => key.as_string().unwrap_or_else(|| "<key>".to_string())
+    }
+}
+
+/// Decodes the data both with this module and with Pike's `decode_value()`,
+/// and checks that the results are equal. Also checks that Pike decodes the
+/// data written by `encode()` to the same value. Returns the decoded value.
+pub fn cross_check(data: &[u8], ctx: &PikeContext)
+-> Result<Value, PikeError> {
+    let value = decode(data)?;
+    let decode_value = ctx.resolv("decode_value")?;
+
+    let thing = decode_value.call(
+        vec![PikeString::from_vec_slice(data, ctx).into()], ctx)?;
+    let pike_value = Value::from_thing(&thing, ctx)?;
+    if !value.same_as(&pike_value) {
+        return Err(PikeError::Generic(format!(
+            "decode() returned {:?}, but decode_value() returned {:?}.",
+            value, pike_value)));
+    }
+
+    let encoded = encode(&value);
+    let thing = decode_value.call(
+        vec![PikeString::from_vec(encoded, ctx).into()], ctx)?;
+    let pike_value = Value::from_thing(&thing, ctx)?;
+    if !value.same_as(&pike_value) {
+        return Err(PikeError::Generic(format!(
+            "decode_value() of encode({:?}) returned {:?}.",
+            value, pike_value)));
+    }
+    Ok(value)
+}
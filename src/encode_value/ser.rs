@@ -0,0 +1,354 @@
+use ::serde::ser::{self, Serialize};
+use super::*;
+
+/// Serializes a Rust value to the format of Pike's `encode_value()`.
+pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, Error>
+where T: ?Sized + Serialize {
+    Ok(encode(&value.serialize(Serializer)?))
+}
+
+/// A serde Serializer that creates a `Value`. Like `serialization::to_pike()`
+/// with the default options, structs and maps become mappings, enums are
+/// externally tagged, and bools, `None` and `()` become ints.
+pub struct Serializer;
+
+impl Serializer {
+    fn string(s: &str) -> Value {
+        Value::from(s)
+    }
+
+    // Wraps the content of a variant as ([ "Variant": content ]).
+    fn variant(variant: &str, content: Value) -> Value {
+        Value::Mapping(vec![(Self::string(variant), content)])
+    }
+
+    fn wide_int(negative: bool, abs: u128) -> Value {
+        Value::from_int_bytes_be(negative, &abs.to_be_bytes())
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value, Error> {
+        Ok(Self::wide_int(v < 0, v.wrapping_abs() as u128))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        Ok(Self::wide_int(false, v.into()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, Error> {
+        Ok(Self::wide_int(false, v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::String(vec![v as i32]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Self::string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::String(v.iter().map(|&b| i32::from(b)).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Int(0))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value, Error>
+    where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Int(0))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str)
+    -> Result<Value, Error> {
+        Ok(Value::Int(0))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str) -> Result<Value, Error> {
+        Ok(Self::string(variant))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T)
+    -> Result<Value, Error>
+    where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(self, _name: &'static str,
+        _variant_index: u32, variant: &'static str, value: &T)
+    -> Result<Value, Error>
+    where T: ?Sized + Serialize {
+        let content = value.serialize(self).map_err(|e| e.at_key(variant))?;
+        Ok(Self::variant(variant, content))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer::new(len.unwrap_or(0), None))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer::new(len, None))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize)
+    -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer::new(len, None))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer::new(len, Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer::new(len.unwrap_or(0), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize)
+    -> Result<MapSerializer, Error> {
+        Ok(MapSerializer::new(len, None))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, len: usize) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer::new(len, Some(variant)))
+    }
+}
+
+/// Builds an array from a sequence, tuple or tuple variant.
+pub struct SeqSerializer {
+    values: Vec<Value>,
+    variant: Option<&'static str>
+}
+
+impl SeqSerializer {
+    fn new(capacity: usize, variant: Option<&'static str>) -> Self {
+        SeqSerializer { values: Vec::with_capacity(capacity), variant }
+    }
+
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let index = self.values.len();
+        let value = value.serialize(Serializer).map_err(|e| {
+            match self.variant {
+                Some(variant) => e.at_index(index).at_key(variant),
+                None => e.at_index(index)
+            }
+        })?;
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, Error> {
+        let res = Value::Array(self.values);
+        match self.variant {
+            Some(variant) => Ok(Serializer::variant(variant, res)),
+            None => Ok(res)
+        }
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+/// Builds a mapping from a map, struct or struct variant.
+pub struct MapSerializer {
+    entries: Vec<(Value, Value)>,
+    next_key: Option<Value>,
+    variant: Option<&'static str>
+}
+
+impl MapSerializer {
+    fn new(capacity: usize, variant: Option<&'static str>) -> Self {
+        MapSerializer {
+            entries: Vec::with_capacity(capacity),
+            next_key: None,
+            variant
+        }
+    }
+
+    fn insert<T>(&mut self, key: Value, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let value = value.serialize(Serializer).map_err(|e| {
+            let e = e.at_key(describe_key(&key));
+            match self.variant {
+                Some(variant) => e.at_key(variant),
+                None => e
+            }
+        })?;
+        self.entries.push((key, value));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, Error> {
+        let res = Value::Mapping(self.entries);
+        match self.variant {
+            Some(variant) => Ok(Serializer::variant(variant, res)),
+            None => Ok(res)
+        }
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.next_key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let key = self.next_key.take().ok_or_else(|| {
+            Error::new("serialize_value called before serialize_key")
+        })?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T)
+    -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.insert(Serializer::string(key), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T)
+    -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.insert(Serializer::string(key), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
@@ -0,0 +1,311 @@
+use ::interpreter::{PikeContext, PikeError};
+use ::traits::*;
+use ::types::*;
+
+/// A value that can be stored in the `encode_value()` format.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Bignum(Bignum),
+    Float(f64),
+    /// The characters of a string. Pike strings may contain characters that
+    /// aren't valid Unicode, including negative ones.
+    String(Vec<i32>),
+    Array(Vec<Value>),
+    Mapping(Vec<(Value, Value)>),
+    Multiset(Vec<Value>)
+}
+
+/// An integer that doesn't fit in an i64, as a sign and a big-endian
+/// magnitude without leading zeros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bignum {
+    pub negative: bool,
+    pub magnitude: Vec<u8>
+}
+
+impl Value {
+    /// Returns an int from a sign (true if negative) and a big-endian
+    /// magnitude. The result is a `Value::Int` if the value fits in an i64,
+    /// and a `Value::Bignum` otherwise.
+    pub fn from_int_bytes_be(negative: bool, magnitude: &[u8]) -> Value {
+        let first_nonzero = magnitude.iter().position(|&b| b != 0)
+            .unwrap_or(magnitude.len());
+        let magnitude = &magnitude[first_nonzero..];
+        if magnitude.len() <= 8 {
+            let abs = magnitude.iter()
+                .fold(0i128, |acc, &b| acc << 8 | i128::from(b));
+            let i = if negative { -abs } else { abs };
+            if i >= i128::from(i64::min_value()) &&
+                i <= i128::from(i64::max_value()) {
+                return Value::Int(i as i64);
+            }
+        }
+        Value::Bignum(Bignum { negative, magnitude: magnitude.to_vec() })
+    }
+
+    /// Returns the characters of a string as a Rust string, or None if the
+    /// value isn't a string or contains characters that aren't valid
+    /// Unicode.
+    pub fn as_string(&self) -> Option<String> {
+        match *self {
+            Value::String(ref chars) => chars.iter()
+                .map(|&c| ::std::char::from_u32(c as u32))
+                .collect(),
+            _ => None
+        }
+    }
+
+    /// Returns the characters of an 8-bit string, or None if the value isn't
+    /// a string or contains wider characters.
+    pub fn as_bytes(&self) -> Option<Vec<u8>> {
+        match *self {
+            Value::String(ref chars) => chars.iter()
+                .map(|&c| {
+                    if (0..=0xff).contains(&c) { Some(c as u8) } else { None }
+                })
+                .collect(),
+            _ => None
+        }
+    }
+
+    /// Creates the corresponding Pike thing.
+    pub fn to_thing(&self, ctx: &PikeContext) -> Result<PikeThing, PikeError> {
+        let res = match *self {
+            Value::Int(i) => PikeThing::try_from_with_ctx(i as i128, ctx)?,
+            Value::Bignum(ref b) => {
+                PikeThing::from_int_bytes_be(b.negative, &b.magnitude, ctx)?
+            },
+            Value::Float(f) => f.into(),
+            Value::String(ref chars) => {
+                let mut builder = PikeStringBuilder::with_capacity(chars.len());
+                for &c in chars {
+                    builder.push_code_point(c);
+                }
+                builder.finish(ctx).into()
+            },
+            Value::Array(ref values) => {
+                let mut a = PikeArray::with_capacity(values.len(), ctx);
+                for v in values {
+                    a.append(v.to_thing(ctx)?);
+                }
+                a.into()
+            },
+            Value::Mapping(ref entries) => {
+                let m = PikeMapping::with_capacity(entries.len(), ctx);
+                for (k, v) in entries {
+                    m.insert(k.to_thing(ctx)?, v.to_thing(ctx)?);
+                }
+                m.into()
+            },
+            Value::Multiset(ref values) => {
+                let m = PikeMultiset::with_capacity(values.len(), ctx);
+                for v in values {
+                    m.insert(v.to_thing(ctx)?);
+                }
+                m.into()
+            }
+        };
+        Ok(res)
+    }
+
+    /// Creates a value from a Pike thing. UNDEFINED becomes 0.
+    pub fn from_thing(thing: &PikeThing, ctx: &PikeContext)
+    -> Result<Value, PikeError> {
+        Self::from_thing_inner(thing, &mut Vec::new(), ctx)
+    }
+
+    // The containers being converted are kept in visiting, to detect cycles.
+    fn from_thing_inner(thing: &PikeThing, visiting: &mut Vec<usize>,
+        ctx: &PikeContext) -> Result<Value, PikeError> {
+        let ptr = match thing {
+            PikeThing::Array(a_ref) => Some(a_ref.as_mut_ptr() as usize),
+            PikeThing::Mapping(m_ref) => Some(m_ref.as_mut_ptr() as usize),
+            PikeThing::Multiset(m_ref) => Some(m_ref.as_mut_ptr() as usize),
+            _ => None
+        };
+        if let Some(ptr) = ptr {
+            if visiting.contains(&ptr) {
+                return Err(PikeError::Args(
+                    "Cyclic data can't be encoded.".to_string()));
+            }
+            visiting.push(ptr);
+        }
+        let mut convert = |thing: &PikeThing| {
+            Self::from_thing_inner(thing, visiting, ctx)
+        };
+        let res = match thing {
+            PikeThing::Int(i) => Value::Int(i.integer.into()),
+            PikeThing::Float(f) => Value::Float(f.into()),
+            PikeThing::PikeString(s_ref) => {
+                let s: PikeString = s_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                Value::String(s.code_points().collect())
+            },
+            PikeThing::Array(a_ref) => {
+                let a: PikeArray = a_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                let values: Result<Vec<_>, _> =
+                    a.into_iter().map(|v| convert(&v)).collect();
+                Value::Array(values?)
+            },
+            PikeThing::Mapping(m_ref) => {
+                let m: PikeMapping = m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                let entries: Result<Vec<_>, PikeError> = m.into_iter()
+                    .map(|(k, v)| Ok((convert(&k)?, convert(&v)?)))
+                    .collect();
+                Value::Mapping(entries?)
+            },
+            PikeThing::Multiset(m_ref) => {
+                let m: PikeMultiset =
+                    m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                let values: Result<Vec<_>, _> =
+                    m.into_iter().map(|v| convert(&v)).collect();
+                Value::Multiset(values?)
+            },
+            PikeThing::Undefined => Value::Int(0),
+            PikeThing::Object(_) if PikeBignum::from_thing(thing, ctx).is_some() => {
+                let (negative, magnitude) = thing.to_int_bytes_be(ctx)?;
+                Value::from_int_bytes_be(negative, &magnitude)
+            },
+            _ => return Err(PikeError::Args(
+                "Only ints, floats, strings, arrays, mappings and multisets \
+                can be encoded.".to_string()))
+        };
+        if ptr.is_some() {
+            visiting.pop();
+        }
+        Ok(res)
+    }
+
+    // Compares values like Pike would compare the things they represent, i.e.
+    // mappings and multisets regardless of order.
+    pub(crate) fn same_as(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Bignum(a), Value::Bignum(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => {
+                a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+            },
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() &&
+                    a.iter().zip(b).all(|(a, b)| a.same_as(b))
+            },
+            (Value::Mapping(a), Value::Mapping(b)) => {
+                same_unordered(a, b, |a, b| a.0.same_as(&b.0) && a.1.same_as(&b.1))
+            },
+            (Value::Multiset(a), Value::Multiset(b)) => {
+                same_unordered(a, b, Value::same_as)
+            },
+            _ => false
+        }
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Int(_) | Value::Bignum(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Mapping(_) => "mapping",
+            Value::Multiset(_) => "multiset"
+        }
+    }
+}
+
+// Returns true if every element in a has a distinct equal element in b.
+fn same_unordered<T, F>(a: &[T], b: &[T], same: F) -> bool
+where F: Fn(&T, &T) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut used = vec![false; b.len()];
+    a.iter().all(|x| {
+        match (0..b.len()).find(|&i| !used[i] && same(x, &b[i])) {
+            Some(i) => {
+                used[i] = true;
+                true
+            },
+            None => false
+        }
+    })
+}
+
+impl Bignum {
+    /// Returns the digits in base 36, with a leading "-" if negative, as
+    /// written by `Gmp.mpz->digits(36)`.
+    pub fn to_base36(&self) -> String {
+        let mut magnitude = self.magnitude.clone();
+        let mut digits = Vec::new();
+        while !magnitude.is_empty() {
+            // Divide by 36, keeping the remainder as the next digit.
+            let mut rem = 0u32;
+            for b in magnitude.iter_mut() {
+                let cur = rem << 8 | u32::from(*b);
+                *b = (cur / 36) as u8;
+                rem = cur % 36;
+            }
+            digits.push(::std::char::from_digit(rem, 36).unwrap_or('0'));
+            let first_nonzero = magnitude.iter().position(|&b| b != 0)
+                .unwrap_or(magnitude.len());
+            magnitude.drain(..first_nonzero);
+        }
+        if digits.is_empty() {
+            digits.push('0');
+        }
+        if self.negative {
+            digits.push('-');
+        }
+        digits.iter().rev().collect()
+    }
+
+    /// Parses digits in base 36, with an optional leading "-". Returns None
+    /// if there are invalid digits.
+    pub fn from_base36(s: &str) -> Option<Self> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s)
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let mut magnitude: Vec<u8> = Vec::new();
+        for c in digits.chars() {
+            // Multiply by 36 and add the digit.
+            let mut carry = c.to_digit(36)?;
+            for b in magnitude.iter_mut().rev() {
+                let cur = u32::from(*b) * 36 + carry;
+                *b = cur as u8;
+                carry = cur >> 8;
+            }
+            while carry > 0 {
+                magnitude.insert(0, carry as u8);
+                carry >>= 8;
+            }
+        }
+        Some(Bignum { negative, magnitude })
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::String(s.chars().map(|c| c as i32).collect())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::from(s.as_str())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
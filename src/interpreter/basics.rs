@@ -6,6 +6,7 @@ use ::std::sync::Mutex;
 use ::std::cell::RefCell;
 use ::std::ptr;
 use ::traits::DropWithContext;
+use ::interpreter::PikeError;
 
 use ::types::*;
 use ::ffi::*;
@@ -159,3 +160,13 @@ impl PikeContext {
         }
     }
 }
+
+impl PikeContext {
+    /// Resolves an identifier such as "Gmp.mpz" or "Val.null" through the
+    /// master object, like Pike's compiler does.
+    pub fn resolv(&self, name: &str) -> Result<PikeThing, PikeError> {
+        let pike_master = PikeObject::<()>::get_master(self);
+        let name_thing = PikeThing::from(PikeString::from_str_slice(name, self));
+        pike_master.call_func("resolv", vec![&name_thing])
+    }
+}
@@ -15,7 +15,7 @@ fn describe_pike_error(pike_error: &PikeThing, ctx: &PikeContext) -> String {
     if let Ok(pt) = desc_res {
         if let PikeThing::PikeString(str_ref) = pt {
             let pike_string: PikeString = str_ref.into_with_ctx(ctx);
-            return pike_string.into();
+            return pike_string.chars().collect();
         }
     }
     "Failed to describe error".to_string()
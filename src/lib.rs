@@ -32,12 +32,16 @@ mod macros;
 
 pub mod traits;
 pub mod types;
+pub mod serialization;
+pub mod encode_value;
 
 pub mod module {
   pub use pike_macros::init_pike_module as init_pike_module;
   pub use pike_macros::pike_func_inits as pike_func_inits;
   pub use pike_macros::pike_export as pike_export;
+  pub use pike_macros::{ToPike, FromPike};
   pub use interpreter::PikeError as PikeError;
+  pub use serialization::Error as ConversionError;
 
   pub use interpreter::prepare_error_message as prepare_error_message;
 
@@ -89,10 +89,7 @@ macro_rules! define_from_impls_with_storage {
 
         impl<TStorage> From<$reftype<TStorage>> for PikeThing {
             fn from(f: $reftype<TStorage>) -> Self {
-                let untyped_obj: $reftype<()> = unsafe {
-                    std::mem::transmute(f)
-                };
-                PikeThing::$pikethingtype(untyped_obj)
+                PikeThing::$pikethingtype(f.into_untyped())
             }
         }
 
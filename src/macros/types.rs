@@ -72,6 +72,17 @@ macro_rules! refcounted_type_with_storage {
             }
         }
 
+        impl<TStorage> $reftype<TStorage> {
+            /// Erases the storage type of this reference.
+            pub fn into_untyped(self) -> $reftype<()> {
+                let ptr = self.ptr;
+                // Our contribution to the reference counter is transferred to
+                // the untyped reference.
+                ::std::mem::forget(self);
+                $reftype { ptr, _phantom: PhantomData }
+            }
+        }
+
         impl<TStorage> CloneWithCtx for $reftype<TStorage> {
             fn clone_with_ctx<'ctx>(&self, _ctx: &'ctx PikeContext) -> Self {
                 unsafe { (*self.ptr).refs += 1 };
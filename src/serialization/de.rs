@@ -0,0 +1,408 @@
+use ::interpreter::{PikeContext, PikeError};
+use ::traits::*;
+use ::types::*;
+use ::serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer,
+    Visitor};
+use super::{Error, Options, EnumRepr, ValConstant, describe_key};
+use ::std::convert::TryFrom;
+
+/// Converts a Pike value to a Rust value, using the default options.
+pub fn from_pike<T>(thing: PikeThing, ctx: &PikeContext) -> Result<T, PikeError>
+where T: DeserializeOwned {
+    from_pike_with_options(thing, &Options::default(), ctx)
+}
+
+/// Converts a Pike value to a Rust value. Errors include the path to the
+/// offending element.
+pub fn from_pike_with_options<T>(thing: PikeThing, options: &Options,
+    ctx: &PikeContext) -> Result<T, PikeError>
+where T: DeserializeOwned {
+    Ok(T::deserialize(Deserializer::new(thing, options, ctx))?)
+}
+
+/// A serde Deserializer that reads from a Pike value.
+pub struct Deserializer<'a, 'ctx> {
+    thing: PikeThing,
+    options: &'a Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'a, 'ctx> Deserializer<'a, 'ctx> {
+    pub fn new(thing: PikeThing, options: &'a Options, ctx: &'ctx PikeContext)
+    -> Self {
+        Deserializer { thing, options, ctx }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::new(format!("Expected {}, got {}.", expected,
+            self.thing.type_name()))
+    }
+
+    fn visit_bignum<'de, V>(&self, bignum: PikeBignum, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        if let Ok(i) = bignum.to_integer::<i64>() {
+            visitor.visit_i64(i)
+        } else if let Ok(u) = bignum.to_integer::<u64>() {
+            visitor.visit_u64(u)
+        } else if let Ok(i) = bignum.to_integer::<i128>() {
+            visitor.visit_i128(i)
+        } else if let Ok(u) = bignum.to_integer::<u128>() {
+            visitor.visit_u128(u)
+        } else {
+            Err(Error::new("Integer is too large."))
+        }
+    }
+
+    fn enum_access(self, name: &str) -> Result<Enum<'a, 'ctx>, Error> {
+        let (variant, content) = match (&self.options.enum_repr, self.thing) {
+            (EnumRepr::External, PikeThing::PikeString(s_ref)) => {
+                (string_from_ref(s_ref, self.ctx)?, None)
+            },
+            (EnumRepr::External, PikeThing::Mapping(m_ref)) => {
+                let m: PikeMapping = m_ref.into_with_ctx(self.ctx);
+                if m.len() != 1 {
+                    return Err(Error::new(format!(
+                        "Expected a mapping with a single variant of {}.",
+                        name)));
+                }
+                match m.into_iter().next() {
+                    Some((PikeThing::PikeString(key), val)) => {
+                        (string_from_ref(key, self.ctx)?, Some(val))
+                    },
+                    _ => return Err(Error::new(format!(
+                        "Expected a string variant name of {}.", name)))
+                }
+            },
+            (EnumRepr::Internal { tag }, PikeThing::Mapping(m_ref)) => {
+                let m: PikeMapping = m_ref.into_with_ctx(self.ctx);
+                let variant = tag_value(&m, tag, name, self.ctx)?;
+                // The content is the mapping without the tag.
+                let content = PikeMapping::with_capacity(m.len(), self.ctx);
+                for (key, val) in &m {
+                    if describe_key(&key, self.ctx) != *tag {
+                        content.insert(key, val);
+                    }
+                }
+                (variant, Some(content.into()))
+            },
+            (EnumRepr::Adjacent { tag, content }, PikeThing::Mapping(m_ref)) => {
+                let m: PikeMapping = m_ref.into_with_ctx(self.ctx);
+                let variant = tag_value(&m, tag, name, self.ctx)?;
+                (variant, m.get_str(content))
+            },
+            (EnumRepr::Untagged, _) => {
+                return Err(Error::new(format!(
+                    "Untagged enum {} can't be deserialized, use \
+                    #[serde(untagged)] instead.", name)))
+            },
+            (_, thing) => {
+                return Err(Error::new(format!(
+                    "Expected enum {}, got {}.", name, thing.type_name())))
+            }
+        };
+        Ok(Enum { variant, content, options: self.options, ctx: self.ctx })
+    }
+}
+
+impl<'de, 'a, 'ctx> de::Deserializer<'de> for Deserializer<'a, 'ctx> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.thing {
+            PikeThing::Int(ref i) => visitor.visit_i64(i.integer.into()),
+            PikeThing::Float(ref f) => visitor.visit_f64(f.into()),
+            PikeThing::PikeString(ref s_ref) => {
+                visitor.visit_string(string_from_ref(s_ref.clone_with_ctx(self.ctx),
+                    self.ctx)?)
+            },
+            PikeThing::Array(ref a_ref) => {
+                let a: PikeArray = a_ref.clone_with_ctx(self.ctx)
+                    .into_with_ctx(self.ctx);
+                visitor.visit_seq(SeqAccess::new(a.into_iter(), &self))
+            },
+            PikeThing::Multiset(ref m_ref) => {
+                let m: PikeMultiset = m_ref.clone_with_ctx(self.ctx)
+                    .into_with_ctx(self.ctx);
+                visitor.visit_seq(SeqAccess::new(m.into_iter(), &self))
+            },
+            PikeThing::Mapping(ref m_ref) => {
+                let m: PikeMapping = m_ref.clone_with_ctx(self.ctx)
+                    .into_with_ctx(self.ctx);
+                visitor.visit_map(MapAccess::new(m.into_iter(), &self))
+            },
+            PikeThing::Undefined => visitor.visit_unit(),
+            PikeThing::Object(_) => {
+                if let Some(bignum) = PikeBignum::from_thing(&self.thing, self.ctx) {
+                    return self.visit_bignum(bignum, visitor);
+                }
+                match ValConstant::from_thing(&self.thing, self.ctx) {
+                    Some(ValConstant::Null) => visitor.visit_unit(),
+                    Some(ValConstant::True) => visitor.visit_bool(true),
+                    Some(ValConstant::False) => visitor.visit_bool(false),
+                    None => Err(self.unexpected("a serializable value"))
+                }
+            },
+            _ => Err(self.unexpected("a serializable value"))
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.thing {
+            PikeThing::Int(ref i) => visitor.visit_bool(i.integer != 0),
+            PikeThing::Object(_) => {
+                match ValConstant::from_thing(&self.thing, self.ctx) {
+                    Some(ValConstant::True) => visitor.visit_bool(true),
+                    Some(ValConstant::False) => visitor.visit_bool(false),
+                    _ => Err(self.unexpected("a bool"))
+                }
+            },
+            _ => Err(self.unexpected("a bool"))
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let is_none = match self.thing {
+            PikeThing::Undefined => true,
+            PikeThing::Object(_) => {
+                ValConstant::from_thing(&self.thing, self.ctx) ==
+                    Some(ValConstant::Null)
+            },
+            _ => false
+        };
+        if is_none {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        match self.thing {
+            PikeThing::Undefined => visitor.visit_unit(),
+            PikeThing::Int(ref i) if i.integer == 0 => visitor.visit_unit(),
+            PikeThing::Object(_) if ValConstant::from_thing(&self.thing,
+                self.ctx) == Some(ValConstant::Null) => visitor.visit_unit(),
+            _ => Err(self.unexpected("UNDEFINED or Val.null"))
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        if let PikeThing::PikeString(ref s_ref) = self.thing {
+            let s: PikeString = s_ref.clone_with_ctx(self.ctx)
+                .into_with_ctx(self.ctx);
+            return match s.as_bytes() {
+                Some(bytes) => visitor.visit_byte_buf(bytes.to_vec()),
+                None => Err(Error::new("Expected an 8-bit string, got a wide string."))
+            };
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(self, name: &'static str,
+        _variants: &'static [&'static str], visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        visitor.visit_enum(self.enum_access(name)?)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string seq
+        tuple tuple_struct map struct identifier
+    }
+}
+
+// Iterates the elements of an array or a multiset.
+struct SeqAccess<'a, 'ctx, I> {
+    iter: I,
+    index: usize,
+    options: &'a Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'a, 'ctx, I> SeqAccess<'a, 'ctx, I> {
+    fn new(iter: I, de: &Deserializer<'a, 'ctx>) -> Self {
+        SeqAccess { iter, index: 0, options: de.options, ctx: de.ctx }
+    }
+}
+
+impl<'de, 'a, 'ctx, I> de::SeqAccess<'de> for SeqAccess<'a, 'ctx, I>
+where I: Iterator<Item = PikeThing> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T)
+    -> Result<Option<T::Value>, Error>
+    where T: DeserializeSeed<'de> {
+        let thing = match self.iter.next() {
+            Some(thing) => thing,
+            None => return Ok(None)
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(Deserializer::new(thing, self.options, self.ctx))
+            .map(Some)
+            .map_err(|e| e.at_index(index))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        match self.iter.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None
+        }
+    }
+}
+
+// Iterates the entries of a mapping.
+struct MapAccess<'a, 'ctx, I> {
+    iter: I,
+    value: Option<(String, PikeThing)>,
+    options: &'a Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'a, 'ctx, I> MapAccess<'a, 'ctx, I> {
+    fn new(iter: I, de: &Deserializer<'a, 'ctx>) -> Self {
+        MapAccess { iter, value: None, options: de.options, ctx: de.ctx }
+    }
+}
+
+impl<'de, 'a, 'ctx, I> de::MapAccess<'de> for MapAccess<'a, 'ctx, I>
+where I: Iterator<Item = (PikeThing, PikeThing)> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where K: DeserializeSeed<'de> {
+        let (key, value) = match self.iter.next() {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+        let key_desc = describe_key(&key, self.ctx);
+        let res = seed.deserialize(Deserializer::new(key, self.options, self.ctx))
+            .map_err(|e| e.at_key(key_desc.clone()))?;
+        self.value = Some((key_desc, value));
+        Ok(Some(res))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where V: DeserializeSeed<'de> {
+        let (key_desc, value) = self.value.take().ok_or_else(|| {
+            Error::new("next_value_seed called before next_key_seed")
+        })?;
+        seed.deserialize(Deserializer::new(value, self.options, self.ctx))
+            .map_err(|e| e.at_key(key_desc))
+    }
+}
+
+// The variant name and content of an enum value.
+struct Enum<'a, 'ctx> {
+    variant: String,
+    content: Option<PikeThing>,
+    options: &'a Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'a, 'ctx> Enum<'a, 'ctx> {
+    fn content(self) -> Result<(String, Deserializer<'a, 'ctx>), Error> {
+        match self.content {
+            Some(content) => {
+                Ok((self.variant,
+                    Deserializer::new(content, self.options, self.ctx)))
+            },
+            None => Err(Error::new(format!("Variant {} has no content.",
+                self.variant)))
+        }
+    }
+}
+
+impl<'de, 'a, 'ctx> de::EnumAccess<'de> for Enum<'a, 'ctx> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where V: DeserializeSeed<'de> {
+        let variant: de::value::StrDeserializer<Error> =
+            self.variant.as_str().into_deserializer();
+        let res = seed.deserialize(variant)?;
+        Ok((res, self))
+    }
+}
+
+impl<'de, 'a, 'ctx> de::VariantAccess<'de> for Enum<'a, 'ctx> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // A variant without content is given to the seed as a unit, so that
+    // self-describing visitors such as PikeThing's can tell unit variants
+    // apart. Other seeds reject the unit.
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where T: DeserializeSeed<'de> {
+        if self.content.is_none() {
+            let unit: de::value::UnitDeserializer<Error> = ().into_deserializer();
+            return seed.deserialize(unit).map_err(|e| e.at_key(self.variant));
+        }
+        let (variant, de) = self.content()?;
+        seed.deserialize(de).map_err(|e| e.at_key(variant))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let (variant, de) = self.content()?;
+        de::Deserializer::deserialize_seq(de, visitor)
+            .map_err(|e| e.at_key(variant))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V)
+    -> Result<V::Value, Error>
+    where V: Visitor<'de> {
+        let (variant, de) = self.content()?;
+        de::Deserializer::deserialize_map(de, visitor)
+            .map_err(|e| e.at_key(variant))
+    }
+}
+
+fn string_from_ref(s_ref: PikeStringRef, ctx: &PikeContext)
+-> Result<String, Error> {
+    let s: PikeString = s_ref.into_with_ctx(ctx);
+    Ok(String::try_from(s)?)
+}
+
+// Returns the variant name in the tag entry of a mapping.
+fn tag_value(m: &PikeMapping, tag: &str, name: &str, ctx: &PikeContext)
+-> Result<String, Error> {
+    match m.get_str(tag) {
+        Some(PikeThing::PikeString(s_ref)) => string_from_ref(s_ref, ctx),
+        _ => Err(Error::new(format!("Expected a {} tag with a variant of {}.",
+            tag, name)))
+    }
+}
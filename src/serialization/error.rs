@@ -0,0 +1,88 @@
+use ::interpreter::PikeError;
+use ::serde::{ser, de};
+use ::std::fmt;
+
+/// An error from converting between Rust and Pike values. The path points
+/// out the offending element, e.g. `.servers[2].port`.
+#[derive(Debug)]
+pub struct Error {
+    msg: String,
+    path: Vec<PathSegment>
+}
+
+/// A step in the path to an element: a mapping key or an array index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize)
+}
+
+impl Error {
+    pub fn new<T: fmt::Display>(msg: T) -> Self {
+        Error { msg: msg.to_string(), path: Vec::new() }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn path(&self) -> &[PathSegment] {
+        &self.path
+    }
+
+    /// Adds a mapping key to the start of the path, while unwinding from the
+    /// element where the error occurred.
+    pub fn at_key<K: fmt::Display>(mut self, key: K) -> Self {
+        self.path.insert(0, PathSegment::Key(key.to_string()));
+        self
+    }
+
+    /// Adds an array index to the start of the path.
+    pub fn at_index(mut self, index: usize) -> Self {
+        self.path.insert(0, PathSegment::Index(index));
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)?;
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        write!(f, " at ")?;
+        for segment in &self.path {
+            match segment {
+                PathSegment::Key(key) => write!(f, ".{}", key)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ::std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg)
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg)
+    }
+}
+
+impl From<PikeError> for Error {
+    fn from(err: PikeError) -> Self {
+        Error::new(err)
+    }
+}
+
+impl From<Error> for PikeError {
+    fn from(err: Error) -> Self {
+        PikeError::Generic(err.to_string())
+    }
+}
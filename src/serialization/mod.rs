@@ -0,0 +1,181 @@
+//! Conversion between Rust values and Pike values through serde, without
+//! going through an intermediate format.
+//!
+//! `to_pike()` converts any `Serialize` value to a `PikeThing`. Structs and
+//! maps become mappings, sequences and tuples become arrays, and bytes become
+//! 8-bit strings. The representation of enums, `None` and bools can be
+//! configured with `Options`.
+//!
+//! `from_pike()` converts a `PikeThing` to any `Deserialize` type, using the
+//! same representations.
+//!
+//! To create Pike values from another serde format, e.g. JSON, use
+//! `PikeSeed` with the deserializer of that format.
+
+mod de;
+mod error;
+mod ser;
+
+pub use self::de::{from_pike, from_pike_with_options, Deserializer};
+pub use self::error::{Error, PathSegment};
+pub use self::ser::{to_pike, to_pike_with_options, Serializer};
+
+use ::interpreter::PikeContext;
+use ::traits::*;
+use ::types::*;
+
+/// Options for how Rust values are represented as Pike values.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub enum_repr: EnumRepr,
+    pub none_repr: NoneRepr,
+    pub bool_repr: BoolRepr
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            enum_repr: EnumRepr::External,
+            none_repr: NoneRepr::Undefined,
+            bool_repr: BoolRepr::Int
+        }
+    }
+}
+
+/// The representation of enum variants, corresponding to serde's enum
+/// representations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// `([ "Variant": content ])`, or just "Variant" for unit variants.
+    External,
+    /// `([ tag: "Variant", ...fields ])`. Only works for variants containing
+    /// structs or maps.
+    Internal { tag: String },
+    /// `([ tag: "Variant", content: content ])`.
+    Adjacent { tag: String, content: String },
+    /// Only the content.
+    Untagged
+}
+
+/// The representation of `None` and `()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoneRepr {
+    /// Pike's UNDEFINED.
+    Undefined,
+    /// `Val.null`.
+    Null
+}
+
+/// The representation of bools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolRepr {
+    /// 1 and 0.
+    Int,
+    /// `Val.true` and `Val.false`.
+    Val
+}
+
+// Returns the representation of None according to the options.
+pub(crate) fn none_value(options: &Options, ctx: &PikeContext)
+-> Result<PikeThing, Error> {
+    match options.none_repr {
+        NoneRepr::Undefined => Ok(PikeThing::undefined()),
+        NoneRepr::Null => Ok(ctx.resolv("Val.null")?)
+    }
+}
+
+// Returns the representation of a bool according to the options.
+pub(crate) fn bool_value(v: bool, options: &Options, ctx: &PikeContext)
+-> Result<PikeThing, Error> {
+    match options.bool_repr {
+        BoolRepr::Int => Ok(PikeThing::from(v as i32)),
+        BoolRepr::Val => {
+            let name = if v { "Val.true" } else { "Val.false" };
+            Ok(ctx.resolv(name)?)
+        }
+    }
+}
+
+// Represents a unit variant of an enum according to the options.
+pub(crate) fn unit_variant(variant: &str, options: &Options, ctx: &PikeContext)
+-> Result<PikeThing, Error> {
+    let string = |s: &str| PikeThing::from(PikeString::from_str_slice(s, ctx));
+    match options.enum_repr {
+        EnumRepr::External => Ok(string(variant)),
+        EnumRepr::Internal { ref tag } | EnumRepr::Adjacent { ref tag, .. } => {
+            let m = PikeMapping::with_capacity(1, ctx);
+            m.insert(string(tag), string(variant));
+            Ok(m.into())
+        },
+        EnumRepr::Untagged => none_value(options, ctx)
+    }
+}
+
+// Represents the content of an enum variant according to the options.
+pub(crate) fn wrap_variant(variant: &str, content: PikeThing, options: &Options,
+    ctx: &PikeContext) -> Result<PikeThing, Error> {
+    let string = |s: &str| PikeThing::from(PikeString::from_str_slice(s, ctx));
+    let m = PikeMapping::with_capacity(2, ctx);
+    match options.enum_repr {
+        EnumRepr::External => {
+            m.insert(string(variant), content);
+        },
+        EnumRepr::Internal { ref tag } => {
+            match content {
+                PikeThing::Mapping(m_ref) => {
+                    let content: PikeMapping = m_ref.into_with_ctx(ctx);
+                    content.insert(string(tag), string(variant));
+                    return Ok(content.into());
+                },
+                _ => return Err(Error::new(format!(
+                    "Internally tagged variant {} must contain a struct or map.",
+                    variant)))
+            }
+        },
+        EnumRepr::Adjacent { ref tag, content: ref content_key } => {
+            m.insert(string(tag), string(variant));
+            m.insert(string(content_key), content);
+        },
+        EnumRepr::Untagged => return Ok(content)
+    }
+    Ok(m.into())
+}
+
+// Returns a mapping key in a form suitable for error paths.
+pub(crate) fn describe_key(key: &PikeThing, ctx: &PikeContext) -> String {
+    match key {
+        PikeThing::PikeString(s_ref) => {
+            let s: PikeString = s_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+            s.chars().collect()
+        },
+        PikeThing::Int(i) => i.integer.to_string(),
+        _ => "<key>".to_string()
+    }
+}
+
+// The constants in Pike's Val module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValConstant {
+    Null,
+    True,
+    False
+}
+
+impl ValConstant {
+    fn from_thing(thing: &PikeThing, ctx: &PikeContext) -> Option<Self> {
+        let obj_ref = match thing {
+            PikeThing::Object(obj_ref) => obj_ref,
+            _ => return None
+        };
+        let constants = [("Val.null", ValConstant::Null),
+            ("Val.true", ValConstant::True), ("Val.false", ValConstant::False)];
+        for &(name, constant) in &constants {
+            if let Ok(PikeThing::Object(val_ref)) = ctx.resolv(name) {
+                if val_ref.as_mut_ptr() == obj_ref.as_mut_ptr() {
+                    return Some(constant);
+                }
+            }
+        }
+        None
+    }
+}
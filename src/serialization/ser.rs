@@ -0,0 +1,385 @@
+use ::interpreter::{PikeContext, PikeError};
+use ::traits::*;
+use ::types::*;
+use ::serde::ser::{self, Serialize};
+use super::{Error, Options, describe_key, unit_variant, wrap_variant,
+    none_value, bool_value};
+
+/// Converts a Rust value to a Pike value, using the default options.
+pub fn to_pike<T>(value: &T, ctx: &PikeContext) -> Result<PikeThing, PikeError>
+where T: ?Sized + Serialize {
+    to_pike_with_options(value, &Options::default(), ctx)
+}
+
+/// Converts a Rust value to a Pike value.
+pub fn to_pike_with_options<T>(value: &T, options: &Options, ctx: &PikeContext)
+-> Result<PikeThing, PikeError>
+where T: ?Sized + Serialize {
+    let serializer = Serializer::new(options, ctx);
+    Ok(value.serialize(&serializer)?)
+}
+
+/// A serde Serializer that creates Pike values.
+pub struct Serializer<'a, 'ctx> {
+    options: &'a Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'a, 'ctx> Serializer<'a, 'ctx> {
+    pub fn new(options: &'a Options, ctx: &'ctx PikeContext) -> Self {
+        Serializer { options, ctx }
+    }
+
+    fn string(&self, s: &str) -> PikeThing {
+        PikeString::from_str_slice(s, self.ctx).into()
+    }
+
+    fn none(&self) -> Result<PikeThing, Error> {
+        none_value(self.options, self.ctx)
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::Serializer for &'s Serializer<'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    type SerializeSeq = SeqSerializer<'s, 'a, 'ctx>;
+    type SerializeTuple = SeqSerializer<'s, 'a, 'ctx>;
+    type SerializeTupleStruct = SeqSerializer<'s, 'a, 'ctx>;
+    type SerializeTupleVariant = SeqSerializer<'s, 'a, 'ctx>;
+    type SerializeMap = MapSerializer<'s, 'a, 'ctx>;
+    type SerializeStruct = MapSerializer<'s, 'a, 'ctx>;
+    type SerializeStructVariant = MapSerializer<'s, 'a, 'ctx>;
+
+    fn serialize_bool(self, v: bool) -> Result<PikeThing, Error> {
+        bool_value(v, self.options, self.ctx)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<PikeThing, Error> {
+        Ok(PikeThing::try_from_with_ctx(v, self.ctx)?)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<PikeThing, Error> {
+        Ok(PikeThing::try_from_with_ctx(v, self.ctx)?)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<PikeThing, Error> {
+        Ok(PikeThing::try_from_with_ctx(v, self.ctx)?)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<PikeThing, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_char(self, v: char) -> Result<PikeThing, Error> {
+        let mut builder = PikeStringBuilder::with_capacity(1);
+        builder.push(v);
+        Ok(builder.finish(self.ctx).into())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<PikeThing, Error> {
+        Ok(self.string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<PikeThing, Error> {
+        Ok(PikeString::from_vec_slice(v, self.ctx).into())
+    }
+
+    fn serialize_none(self) -> Result<PikeThing, Error> {
+        self.none()
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<PikeThing, Error>
+    where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<PikeThing, Error> {
+        self.none()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str)
+    -> Result<PikeThing, Error> {
+        self.none()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str) -> Result<PikeThing, Error> {
+        unit_variant(variant, self.options, self.ctx)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T)
+    -> Result<PikeThing, Error>
+    where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(self, _name: &'static str,
+        _variant_index: u32, variant: &'static str, value: &T)
+    -> Result<PikeThing, Error>
+    where T: ?Sized + Serialize {
+        let content = value.serialize(self).map_err(|e| e.at_key(variant))?;
+        wrap_variant(variant, content, self.options, self.ctx)
+    }
+
+    fn serialize_seq(self, len: Option<usize>)
+    -> Result<SeqSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(SeqSerializer::new(self, len.unwrap_or(0), None))
+    }
+
+    fn serialize_tuple(self, len: usize)
+    -> Result<SeqSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(SeqSerializer::new(self, len, None))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize)
+    -> Result<SeqSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(SeqSerializer::new(self, len, None))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, len: usize)
+    -> Result<SeqSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(SeqSerializer::new(self, len, Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>)
+    -> Result<MapSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(MapSerializer::new(self, len.unwrap_or(0), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize)
+    -> Result<MapSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(MapSerializer::new(self, len, None))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, len: usize)
+    -> Result<MapSerializer<'s, 'a, 'ctx>, Error> {
+        Ok(MapSerializer::new(self, len, Some(variant)))
+    }
+}
+
+/// Builds an array from a sequence, tuple or tuple variant.
+pub struct SeqSerializer<'s, 'a: 's, 'ctx: 's> {
+    ser: &'s Serializer<'a, 'ctx>,
+    array: PikeArray<'ctx>,
+    variant: Option<&'static str>
+}
+
+impl<'s, 'a, 'ctx> SeqSerializer<'s, 'a, 'ctx> {
+    fn new(ser: &'s Serializer<'a, 'ctx>, capacity: usize,
+        variant: Option<&'static str>) -> Self {
+        SeqSerializer {
+            ser,
+            array: PikeArray::with_capacity(capacity, ser.ctx),
+            variant
+        }
+    }
+
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let index = self.array.len();
+        let thing = value.serialize(self.ser).map_err(|e| {
+            match self.variant {
+                Some(variant) => e.at_index(index).at_key(variant),
+                None => e.at_index(index)
+            }
+        })?;
+        self.array.append(thing);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PikeThing, Error> {
+        let res = PikeThing::from(self.array);
+        match self.variant {
+            Some(variant) => wrap_variant(variant, res, self.ser.options, self.ser.ctx),
+            None => Ok(res)
+        }
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeSeq for SeqSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeTuple for SeqSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeTupleStruct for SeqSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeTupleVariant for SeqSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+/// Builds a mapping from a map, struct or struct variant.
+pub struct MapSerializer<'s, 'a: 's, 'ctx: 's> {
+    ser: &'s Serializer<'a, 'ctx>,
+    mapping: PikeMapping<'ctx>,
+    next_key: Option<PikeThing>,
+    variant: Option<&'static str>
+}
+
+impl<'s, 'a, 'ctx> MapSerializer<'s, 'a, 'ctx> {
+    fn new(ser: &'s Serializer<'a, 'ctx>, capacity: usize,
+        variant: Option<&'static str>) -> Self {
+        MapSerializer {
+            ser,
+            mapping: PikeMapping::with_capacity(capacity, ser.ctx),
+            next_key: None,
+            variant
+        }
+    }
+
+    fn insert<T>(&mut self, key: PikeThing, key_desc: String, value: &T)
+    -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let thing = value.serialize(self.ser).map_err(|e| {
+            match self.variant {
+                Some(variant) => e.at_key(key_desc).at_key(variant),
+                None => e.at_key(key_desc)
+            }
+        })?;
+        self.mapping.insert(key, thing);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PikeThing, Error> {
+        let res = PikeThing::from(self.mapping);
+        match self.variant {
+            Some(variant) => wrap_variant(variant, res, self.ser.options, self.ser.ctx),
+            None => Ok(res)
+        }
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeMap for MapSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        self.next_key = Some(key.serialize(self.ser)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let key = self.next_key.take().ok_or_else(|| {
+            Error::new("serialize_value called before serialize_key")
+        })?;
+        let key_desc = describe_key(&key, self.ser.ctx);
+        self.insert(key, key_desc, value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeStruct for MapSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T)
+    -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let key_thing = self.ser.string(key);
+        self.insert(key_thing, key.to_string(), value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a, 'ctx> ser::SerializeStructVariant for MapSerializer<'s, 'a, 'ctx> {
+    type Ok = PikeThing;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T)
+    -> Result<(), Error>
+    where T: ?Sized + Serialize {
+        let key_thing = self.ser.string(key);
+        self.insert(key_thing, key.to_string(), value)
+    }
+
+    fn end(self) -> Result<PikeThing, Error> {
+        self.finish()
+    }
+}
@@ -0,0 +1,379 @@
+use ::interpreter::PikeContext;
+use ::serialization::{Error, describe_key};
+use ::types::*;
+use super::*;
+
+use ::std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use ::std::convert::TryFrom;
+use ::std::hash::Hash;
+
+/// Conversion of a Rust value to a Pike value.
+///
+/// `#[derive(ToPike)]` implements it for structs, which become mappings from
+/// field names to values, and enums, which are externally tagged like in
+/// `serialization`. Fields can be adjusted with `#[pike(...)]` attributes:
+///
+/// - `rename = "name"` uses another mapping key.
+/// - `skip` leaves the field out.
+///
+/// Values that convert to `UNDEFINED`, like `None`, are left out of the
+/// mapping.
+///
+/// Functions exported with `#[pike_export]` convert their arguments and return
+/// values with `FromPike` and `ToPike` if they are named in
+/// `#[pike_convert(arg, ..., return)]`.
+pub trait ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error>;
+}
+
+/// Conversion of a Pike value to a Rust value.
+///
+/// `#[derive(FromPike)]` implements it for structs and enums, with the same
+/// representations as `#[derive(ToPike)]`. Fields can be adjusted with
+/// `#[pike(...)]` attributes:
+///
+/// - `rename = "name"` uses another mapping key.
+/// - `default` uses `Default::default()` if the key is missing.
+/// - `default = "path"` calls the function at the path if the key is missing.
+/// - `skip` ignores the mapping and uses `Default::default()`.
+///
+/// A missing key is the same as `UNDEFINED`, so `Option` fields are optional.
+/// Fields of tuple structs and variants are missing if the array is too short
+/// to contain them, and skipped fields aren't in the array.
+pub trait FromPike: Sized {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error>;
+}
+
+fn unexpected(expected: &str, thing: &PikeThing) -> Error {
+    Error::new(format!("Expected {}, got {}.", expected, thing.type_name()))
+}
+
+impl<'a, T> ToPike for &'a T where T: ?Sized + ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        (**self).to_pike(ctx)
+    }
+}
+
+impl<T> ToPike for Box<T> where T: ?Sized + ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        (**self).to_pike(ctx)
+    }
+}
+
+impl<T> FromPike for Box<T> where T: FromPike {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        T::from_pike(thing, ctx).map(Box::new)
+    }
+}
+
+impl ToPike for PikeThing {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        Ok(self.clone_with_ctx(ctx))
+    }
+}
+
+impl FromPike for PikeThing {
+    fn from_pike(thing: PikeThing, _ctx: &PikeContext) -> Result<Self, Error> {
+        Ok(thing)
+    }
+}
+
+macro_rules! gen_int_conversion {
+    ($inttype: ident) => {
+        impl ToPike for $inttype {
+            fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+                Ok(PikeThing::try_from_with_ctx(*self, ctx)?)
+            }
+        }
+
+        impl FromPike for $inttype {
+            fn from_pike(thing: PikeThing, ctx: &PikeContext)
+            -> Result<Self, Error> {
+                if let PikeThing::Int(_) | PikeThing::Object(_) = thing {
+                    return Ok($inttype::try_from_with_ctx(thing, ctx)?);
+                }
+                Err(unexpected("an int", &thing))
+            }
+        }
+    };
+}
+
+gen_int_conversion!(u128);
+gen_int_conversion!(u64);
+gen_int_conversion!(u32);
+gen_int_conversion!(u16);
+gen_int_conversion!(u8);
+gen_int_conversion!(usize);
+
+gen_int_conversion!(i128);
+gen_int_conversion!(i64);
+gen_int_conversion!(i32);
+gen_int_conversion!(i16);
+gen_int_conversion!(i8);
+gen_int_conversion!(isize);
+
+macro_rules! gen_float_conversion {
+    ($floattype: ident) => {
+        impl ToPike for $floattype {
+            fn to_pike(&self, _ctx: &PikeContext) -> Result<PikeThing, Error> {
+                Ok(PikeThing::from(*self))
+            }
+        }
+
+        impl FromPike for $floattype {
+            fn from_pike(thing: PikeThing, _ctx: &PikeContext)
+            -> Result<Self, Error> {
+                match thing {
+                    PikeThing::Float(ref f) => Ok(f.into()),
+                    _ => Err(unexpected("a float", &thing))
+                }
+            }
+        }
+    };
+}
+
+gen_float_conversion!(f64);
+gen_float_conversion!(f32);
+
+/// Bools are represented as 1 and 0.
+impl ToPike for bool {
+    fn to_pike(&self, _ctx: &PikeContext) -> Result<PikeThing, Error> {
+        Ok(PikeThing::from(*self as i32))
+    }
+}
+
+/// Any int other than 0 is true.
+impl FromPike for bool {
+    fn from_pike(thing: PikeThing, _ctx: &PikeContext) -> Result<Self, Error> {
+        match thing {
+            PikeThing::Int(ref i) => Ok(i.integer != 0),
+            _ => Err(unexpected("an int", &thing))
+        }
+    }
+}
+
+impl ToPike for str {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        Ok(PikeString::from_str_slice(self, ctx).into())
+    }
+}
+
+impl ToPike for String {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        self.as_str().to_pike(ctx)
+    }
+}
+
+impl FromPike for String {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        match thing {
+            PikeThing::PikeString(s_ref) => {
+                let s: PikeString = s_ref.into_with_ctx(ctx);
+                Ok(String::try_from(s)?)
+            },
+            _ => Err(unexpected("a string", &thing))
+        }
+    }
+}
+
+/// `None` is represented as `UNDEFINED`.
+impl<T> ToPike for Option<T> where T: ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        match *self {
+            Some(ref v) => v.to_pike(ctx),
+            None => Ok(PikeThing::undefined())
+        }
+    }
+}
+
+impl<T> FromPike for Option<T> where T: FromPike {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        match thing {
+            PikeThing::Undefined => Ok(None),
+            _ => T::from_pike(thing, ctx).map(Some)
+        }
+    }
+}
+
+impl<T> ToPike for [T] where T: ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        let mut a = PikeArray::with_capacity(self.len(), ctx);
+        for (index, v) in self.iter().enumerate() {
+            a.append(v.to_pike(ctx).map_err(|e| e.at_index(index))?);
+        }
+        Ok(a.into())
+    }
+}
+
+impl<T> ToPike for Vec<T> where T: ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        self.as_slice().to_pike(ctx)
+    }
+}
+
+impl<T> FromPike for Vec<T> where T: FromPike {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        match thing {
+            PikeThing::Array(a_ref) => {
+                let a: PikeArray = a_ref.into_with_ctx(ctx);
+                a.into_iter().enumerate()
+                    .map(|(index, v)| {
+                        T::from_pike(v, ctx).map_err(|e| e.at_index(index))
+                    })
+                    .collect()
+            },
+            _ => Err(unexpected("an array", &thing))
+        }
+    }
+}
+
+// Converts the entries of a map to a mapping.
+fn mapping_to_pike<'a, K, V, I>(entries: I, len: usize, ctx: &PikeContext)
+-> Result<PikeThing, Error>
+where K: 'a + ToPike, V: 'a + ToPike, I: Iterator<Item = (&'a K, &'a V)> {
+    let m = PikeMapping::with_capacity(len, ctx);
+    for (k, v) in entries {
+        let key = k.to_pike(ctx)?;
+        let key_desc = describe_key(&key, ctx);
+        m.insert(key, v.to_pike(ctx).map_err(|e| e.at_key(key_desc))?);
+    }
+    Ok(m.into())
+}
+
+// Converts the entries of a mapping to a map.
+fn mapping_from_pike<K, V, M>(thing: PikeThing, ctx: &PikeContext)
+-> Result<M, Error>
+where K: FromPike, V: FromPike, M: ::std::iter::FromIterator<(K, V)> {
+    match thing {
+        PikeThing::Mapping(m_ref) => {
+            let m: PikeMapping = m_ref.into_with_ctx(ctx);
+            m.into_iter()
+                .map(|(k, v)| {
+                    let key_desc = describe_key(&k, ctx);
+                    let k = K::from_pike(k, ctx)
+                        .map_err(|e| e.at_key(key_desc.clone()))?;
+                    let v = V::from_pike(v, ctx)
+                        .map_err(|e| e.at_key(key_desc))?;
+                    Ok((k, v))
+                })
+                .collect()
+        },
+        _ => Err(unexpected("a mapping", &thing))
+    }
+}
+
+impl<K, V> ToPike for HashMap<K, V> where K: ToPike + Eq + Hash, V: ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        mapping_to_pike(self.iter(), self.len(), ctx)
+    }
+}
+
+impl<K, V> FromPike for HashMap<K, V>
+where K: FromPike + Eq + Hash, V: FromPike {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        mapping_from_pike(thing, ctx)
+    }
+}
+
+impl<K, V> ToPike for BTreeMap<K, V> where K: ToPike + Ord, V: ToPike {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        mapping_to_pike(self.iter(), self.len(), ctx)
+    }
+}
+
+impl<K, V> FromPike for BTreeMap<K, V> where K: FromPike + Ord, V: FromPike {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        mapping_from_pike(thing, ctx)
+    }
+}
+
+// Converts the elements of a set to a multiset.
+fn multiset_to_pike<'a, T, I>(values: I, len: usize, ctx: &PikeContext)
+-> Result<PikeThing, Error>
+where T: 'a + ToPike, I: Iterator<Item = &'a T> {
+    let m = PikeMultiset::with_capacity(len, ctx);
+    for v in values {
+        m.insert(v.to_pike(ctx)?);
+    }
+    Ok(m.into())
+}
+
+// Converts the elements of a multiset to a set.
+fn multiset_from_pike<T, S>(thing: PikeThing, ctx: &PikeContext)
+-> Result<S, Error>
+where T: FromPike, S: ::std::iter::FromIterator<T> {
+    match thing {
+        PikeThing::Multiset(m_ref) => {
+            let m: PikeMultiset = m_ref.into_with_ctx(ctx);
+            m.into_iter().map(|v| T::from_pike(v, ctx)).collect()
+        },
+        _ => Err(unexpected("a multiset", &thing))
+    }
+}
+
+/// Sets are represented as multisets.
+impl<T> ToPike for HashSet<T> where T: ToPike + Eq + Hash {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        multiset_to_pike(self.iter(), self.len(), ctx)
+    }
+}
+
+impl<T> FromPike for HashSet<T> where T: FromPike + Eq + Hash {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        multiset_from_pike(thing, ctx)
+    }
+}
+
+impl<T> ToPike for BTreeSet<T> where T: ToPike + Ord {
+    fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+        multiset_to_pike(self.iter(), self.len(), ctx)
+    }
+}
+
+impl<T> FromPike for BTreeSet<T> where T: FromPike + Ord {
+    fn from_pike(thing: PikeThing, ctx: &PikeContext) -> Result<Self, Error> {
+        multiset_from_pike(thing, ctx)
+    }
+}
+
+// Tuples are represented as arrays of the same length.
+macro_rules! gen_tuple_conversion {
+    ($len: expr, $($name: ident $index: tt),+) => {
+        impl<$($name),+> ToPike for ($($name,)+) where $($name: ToPike),+ {
+            fn to_pike(&self, ctx: &PikeContext) -> Result<PikeThing, Error> {
+                let mut a = PikeArray::with_capacity($len, ctx);
+                $(
+                    a.append(self.$index.to_pike(ctx)
+                        .map_err(|e| e.at_index($index))?);
+                )+
+                Ok(a.into())
+            }
+        }
+
+        impl<$($name),+> FromPike for ($($name,)+) where $($name: FromPike),+ {
+            fn from_pike(thing: PikeThing, ctx: &PikeContext)
+            -> Result<Self, Error> {
+                let a: PikeArray = match thing {
+                    PikeThing::Array(a_ref) => a_ref.into_with_ctx(ctx),
+                    _ => return Err(unexpected("an array", &thing))
+                };
+                if a.len() != $len {
+                    return Err(Error::new(format!(
+                        "Expected an array of {} elements, got {}.",
+                        $len, a.len())));
+                }
+                Ok(($(
+                    $name::from_pike(a.get($index)?, ctx)
+                        .map_err(|e| e.at_index($index))?,
+                )+))
+            }
+        }
+    };
+}
+
+gen_tuple_conversion!(1, T0 0);
+gen_tuple_conversion!(2, T0 0, T1 1);
+gen_tuple_conversion!(3, T0 0, T1 1, T2 2);
+gen_tuple_conversion!(4, T0 0, T1 1, T2 2, T3 3);
+gen_tuple_conversion!(5, T0 0, T1 1, T2 2, T3 3, T4 4);
+gen_tuple_conversion!(6, T0 0, T1 1, T2 2, T3 3, T4 4, T5 5);
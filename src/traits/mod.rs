@@ -1,4 +1,7 @@
-use ::interpreter::PikeContext;
+use ::interpreter::{PikeContext, PikeError};
+
+mod conversion;
+pub use self::conversion::{ToPike, FromPike};
 
 pub(crate) trait Refcounted<TPtr>: Drop + CloneWithCtx {
     unsafe fn from_ptr(ptr: *mut TPtr) -> Self;
@@ -18,6 +21,13 @@ pub trait IntoWithCtx<'ctx, T>: Sized {
     fn into_with_ctx(self, ctx: &'ctx PikeContext) -> T;
 }
 
+/// Fallible conversion that requires a `PikeContext`, e.g. from a `PikeThing`
+/// to a Rust integer type.
+pub trait TryFromWithCtx<'ctx, T>: Sized {
+    fn try_from_with_ctx(_: T, ctx: &'ctx PikeContext)
+    -> Result<Self, PikeError>;
+}
+
 pub(crate) trait DropWithContext {
     fn drop_with_context(&self, ctx: &PikeContext);
 }
@@ -1,5 +1,8 @@
 mod pike_array;
-pub use self::pike_array::{PikeArray, PikeArrayRef};
+pub use self::pike_array::{PikeArray, PikeArrayRef, PikeArrayIterator};
+
+mod pike_bignum;
+pub use self::pike_bignum::PikeBignum;
 
 mod pike_function;
 pub use self::pike_function::{PikeFunction, PikeFunctionRef};
@@ -11,10 +14,12 @@ mod pike_int;
 pub use self::pike_int::PikeInt;
 
 mod pike_mapping;
-pub use self::pike_mapping::{PikeMapping, PikeMappingRef};
+pub use self::pike_mapping::{PikeMapping, PikeMappingRef,
+    PikeMappingEntry, PikeMappingIterator};
 
 mod pike_multiset;
-pub use self::pike_multiset::{PikeMultiset, PikeMultisetRef};
+pub use self::pike_multiset::{PikeMultiset, PikeMultisetRef,
+    PikeMultisetIterator};
 
 mod pike_object;
 pub use self::pike_object::{PikeObject, PikeObjectRef};
@@ -23,10 +28,16 @@ mod pike_program;
 pub use self::pike_program::{PikeProgram, PikeProgramRef};
 
 mod pike_str;
-pub use self::pike_str::{PikeString, PikeStringRef};
+pub use self::pike_str::{PikeString, PikeStringRef, PikeStringBuilder,
+    PikeStringChars};
 
 mod pike_type;
-pub use self::pike_type::{PikeTypeRef};
+pub use self::pike_type::{PikeTypeRef, PikeType, IntRange, FunctionType,
+    ObjectType};
+
+mod pike_type_parser;
+
+mod range;
 
 mod pike_svalue;
 mod pike_thing;
@@ -1,6 +1,9 @@
 use ::types::type_deps::*;
 use ::serde::ser::*;
 use ::ffi::*;
+use ::types::range::resolve_range;
+use ::std::ops::RangeBounds;
+use ::std::ptr;
 
 #[derive(Debug)]
 pub struct PikeArrayRef {
@@ -60,14 +63,9 @@ impl<'ctx> PikeArray<'ctx> {
 
     pub fn append(&mut self, value: PikeThing) {
         let mut sval: svalue = value.into();
-        let new_ptr;
-        {
-            let old_ref = &self.array_ref;
-            new_ptr = unsafe {
-                append_array(old_ref.as_mut_ptr(), &mut sval)
-            };
-        }
-        self.array_ref = unsafe { PikeArrayRef::from_ptr(new_ptr) };
+        self.replace_ptr(|ptr| unsafe { append_array(ptr, &mut sval) });
+        // append_array() adds its own reference to the value.
+        let _: PikeThing = sval.into();
     }
 
     pub fn len(&self) -> usize {
@@ -79,33 +77,164 @@ impl<'ctx> PikeArray<'ctx> {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Returns the element at the specified index.
+    pub fn get(&self, index: usize) -> Result<PikeThing, PikeError> {
+        self.check_index(index)?;
+        let sval = unsafe { &*self.item_ptr(index) };
+        Ok(PikeThing::from_svalue_ref(sval, self.ctx))
+    }
+
+    /// Replaces the element at the specified index.
+    ///
+    /// Like index assignment in Pike, this modifies the array in place, so
+    /// the change is visible through all references to the array.
+    pub fn set(&mut self, index: usize, value: PikeThing)
+    -> Result<(), PikeError> {
+        self.check_index(index)?;
+        let sval: svalue = value.into();
+        let type_bit = 1 << sval.type_();
+        unsafe {
+            let item = self.item_ptr(index);
+            // The reference held by the old element is transferred to
+            // old_val, which releases it when dropped.
+            let old_val: PikeThing = ptr::read(item).into();
+            ptr::write(item, sval);
+            (*self.array_ref.as_mut_ptr()).type_field |= type_bit;
+            ::std::mem::drop(old_val);
+        }
+        Ok(())
+    }
+
+    /// Inserts an element at the specified index, shifting all elements after
+    /// it to the right. The array is copied first if it is shared.
+    pub fn insert(&mut self, index: usize, value: PikeThing)
+    -> Result<(), PikeError> {
+        if index > self.len() {
+            return Err(self.out_of_range(index));
+        }
+        let mut sval: svalue = value.into();
+        self.replace_ptr(|ptr| unsafe {
+            array_insert(ptr, &mut sval, index as i32)
+        });
+        // array_insert() adds its own reference to the value.
+        let _: PikeThing = sval.into();
+        Ok(())
+    }
+
+    /// Removes and returns the element at the specified index, shifting all
+    /// elements after it to the left. The array is copied first if it is
+    /// shared.
+    pub fn remove(&mut self, index: usize) -> Result<PikeThing, PikeError> {
+        let res = self.get(index)?;
+        self.replace_ptr(|ptr| unsafe { array_remove(ptr, index as i32) });
+        Ok(res)
+    }
+
+    /// Shortens the array to the specified length. Has no effect if the
+    /// array is already shorter than that. The array is copied first if it
+    /// is shared.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.replace_ptr(|ptr| unsafe { array_shrink(ptr, len as isize) });
+        }
+    }
+
+    /// Returns a new array containing the elements in the specified range.
+    pub fn slice<R>(&self, range: R) -> Result<PikeArray<'ctx>, PikeError>
+    where R: RangeBounds<usize> {
+        let (start, end) = resolve_range(&range, self.len(), "array")?;
+        let array_ref = unsafe {
+            PikeArrayRef::from_ptr(slice_array(self.array_ref.as_mut_ptr(),
+                start as isize, end as isize))
+        };
+        Ok(PikeArray { array_ref, ctx: self.ctx })
+    }
+
+    /// Swaps two elements in place.
+    pub fn swap(&mut self, a: usize, b: usize) -> Result<(), PikeError> {
+        self.check_index(a)?;
+        self.check_index(b)?;
+        unsafe { ptr::swap(self.item_ptr(a), self.item_ptr(b)) };
+        Ok(())
+    }
+
+    // Must only be called with an index that has been checked against the
+    // array size.
+    unsafe fn item_ptr(&self, index: usize) -> *mut svalue {
+        (*self.array_ref.as_mut_ptr()).item.add(index)
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), PikeError> {
+        if index < self.len() {
+            Ok(())
+        } else {
+            Err(self.out_of_range(index))
+        }
+    }
+
+    fn out_of_range(&self, index: usize) -> PikeError {
+        if self.is_empty() {
+            return PikeError::Args(format!(
+                "Index {} is out of bounds, the array is empty.", index));
+        }
+        PikeError::Args(format!("Index {} is out of array range 0..{}.",
+            index, self.len() - 1))
+    }
+
+    // Pike's array functions that may reallocate (append_array, array_insert
+    // etc.) consume the reference to the array they are passed and return a
+    // reference to the resulting array, which is a copy if the original was
+    // shared. This replaces our reference accordingly.
+    fn replace_ptr<F>(&mut self, f: F)
+    where F: FnOnce(*mut array) -> *mut array {
+        let new_ptr = f(self.array_ref.as_mut_ptr());
+        let old_ref = ::std::mem::replace(&mut self.array_ref,
+            unsafe { PikeArrayRef::from_ptr(new_ptr) });
+        // Our reference to the old array was consumed by f.
+        ::std::mem::forget(old_ref);
+    }
 }
 
+/// Iterates over the elements of an array by reading its item storage
+/// directly. The iterator holds a reference to the array, so any resizing of it
+/// during iteration will operate on a copy.
 pub struct PikeArrayIterator<'ctx> {
-  iterator: PikeObject<'ctx, ()>
+    array_ref: PikeArrayRef,
+    index: usize,
+    ctx: &'ctx PikeContext
+}
+
+impl<'ctx> PikeArrayIterator<'ctx> {
+    pub(crate) fn new(array_ref: PikeArrayRef, ctx: &'ctx PikeContext) -> Self {
+        PikeArrayIterator { array_ref, index: 0, ctx }
+    }
+
+    fn remaining(&self) -> usize {
+        let size = unsafe { (*self.array_ref.as_mut_ptr()).size as usize };
+        size.saturating_sub(self.index)
+    }
 }
 
 impl<'ctx> Iterator for PikeArrayIterator<'ctx> {
-    type Item = (PikeThing);
+    type Item = PikeThing;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ended = self.iterator.call_func("`!", vec![])
-            .expect("Error calling \"`!\" in iterator");
-        match ended {
-            PikeThing::Int(i) => {
-                if i.integer != 0 {
-                    return None;
-                }
-            }
-            _ => panic!("Wrong type from iterator->`!")
+        // The size is checked on every call since the elements may have been
+        // modified in place since the previous one.
+        if self.remaining() == 0 {
+            return None;
         }
+        let sval = unsafe {
+            &*(*self.array_ref.as_mut_ptr()).item.add(self.index)
+        };
+        self.index += 1;
+        Some(PikeThing::from_svalue_ref(sval, self.ctx))
+    }
 
-        let val = self.iterator.call_func("value", vec![])
-            .expect("Error calling \"value\" in iterator");
-        self.iterator.call_func("next", vec![])
-            .expect("Error calling \"next\" in iterator");
-
-        Some(val)
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
     }
 }
 
@@ -114,18 +243,7 @@ impl<'ctx> IntoIterator for PikeArray<'ctx> {
     type IntoIter = PikeArrayIterator<'ctx>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Array(self.array_ref);
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeArrayIterator::<'ctx> {
-                    iterator: it.into_with_ctx(ctx)
-                }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeArrayIterator::new(self.array_ref, self.ctx)
     }
 }
 
@@ -134,16 +252,7 @@ impl<'a> IntoIterator for &'a PikeArray<'a> {
     type IntoIter = PikeArrayIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Array(self.array_ref.clone_with_ctx(ctx));
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeArrayIterator { iterator: it.into_with_ctx(ctx) }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeArrayIterator::new(self.array_ref.clone_with_ctx(self.ctx), self.ctx)
     }
 }
 
@@ -151,10 +260,8 @@ impl<'a> Serialize for PikeArray<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let mut seq = serializer.serialize_seq(Some(self.len()))?;
-        for v in self {
-            seq.serialize_element(&v.unwrap(self.ctx))?;
-        }
-        seq.end()
+        // Serialized as a thing, to detect cycles in the contents.
+        let thing = PikeThing::Array(self.array_ref.clone_with_ctx(self.ctx));
+        thing.unwrap(self.ctx).serialize(serializer)
     }
 }
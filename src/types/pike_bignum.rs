@@ -0,0 +1,214 @@
+use ::types::type_deps::*;
+use ::std::convert::TryFrom;
+use ::std::os::raw::c_long;
+use ::std::str::FromStr;
+
+/// A Pike bignum, i.e. a `Gmp.mpz` object. Pike represents integers that don't
+/// fit in a native int as bignums.
+#[derive(Debug, Clone)]
+pub struct PikeBignum<'ctx> {
+    object: PikeObject<'ctx, ()>,
+    ctx: &'ctx PikeContext
+}
+
+impl<'ctx> PikeBignum<'ctx> {
+    /// Returns the thing as a bignum if it is a `Gmp.mpz` object.
+    pub fn from_thing(thing: &PikeThing, ctx: &'ctx PikeContext)
+    -> Option<Self> {
+        let obj_ref = match thing {
+            PikeThing::Object(obj_ref) => obj_ref,
+            _ => return None
+        };
+        let prog_ref = match bignum_program(ctx) {
+            Ok(prog_ref) => prog_ref,
+            Err(_) => return None
+        };
+        let is_bignum = unsafe {
+            (*obj_ref.as_mut_ptr()).prog == prog_ref.as_mut_ptr()
+        };
+        if is_bignum {
+            Some(PikeBignum { object: obj_ref.into_with_ctx(ctx), ctx })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the sign (true if negative) and the big-endian magnitude of the
+    /// bignum.
+    pub fn to_bytes_be(&self) -> Result<(bool, Vec<u8>), PikeError> {
+        let hex = self.digits(16)?;
+        let negative = hex.starts_with('-');
+        let nibbles: Vec<u8> = hex.trim_start_matches('-').chars()
+            .map(|c| c.to_digit(16).unwrap_or(0) as u8)
+            .collect();
+        // Pad with a leading zero nibble if needed, and combine pairs of
+        // nibbles into bytes.
+        let padded: Vec<u8> = ::std::iter::repeat(0).take(nibbles.len() % 2)
+            .chain(nibbles)
+            .collect();
+        let bytes = padded.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect();
+        Ok((negative, bytes))
+    }
+
+    /// Returns the value of the bignum as a Rust integer, or an error if it is
+    /// out of range for the type.
+    pub fn to_integer<T>(&self) -> Result<T, PikeError>
+    where T: FromStr {
+        let digits = self.digits(10)?;
+        digits.parse::<T>().map_err(|_| {
+            PikeError::Args(format!("Integer {} is out of range.", digits))
+        })
+    }
+
+    /// Creates a bignum from a string of digits in the specified base (2-36,
+    /// or 256 for a big-endian byte string).
+    fn from_digits(digits: PikeString, base: i32, ctx: &'ctx PikeContext)
+    -> Result<Self, PikeError> {
+        let prog = PikeThing::Program(bignum_program(ctx)?);
+        let res = prog.call(vec![digits.into(), base.into()], ctx)?;
+        Self::from_thing(&res, ctx).ok_or_else(|| {
+            PikeError::Generic("Gmp.mpz() did not return a bignum.".to_string())
+        })
+    }
+
+    /// Returns the digits of the bignum in the specified base, with a leading
+    /// "-" if it is negative.
+    fn digits(&self, base: i32) -> Result<String, PikeError> {
+        match self.object.call_func("digits", vec![&base.into()])? {
+            PikeThing::PikeString(s) => {
+                let pike_str: PikeString = s.into_with_ctx(self.ctx);
+                Ok(pike_str.chars().collect())
+            }
+            _ => Err(PikeError::Generic(
+                "Gmp.mpz->digits() did not return a string.".to_string()))
+        }
+    }
+}
+
+impl<'ctx> From<PikeBignum<'ctx>> for PikeThing {
+    fn from(bignum: PikeBignum) -> Self {
+        bignum.object.into()
+    }
+}
+
+fn bignum_program(ctx: &PikeContext) -> Result<PikeProgramRef<()>, PikeError> {
+    match ctx.resolv("Gmp.mpz")? {
+        PikeThing::Program(prog_ref) => Ok(prog_ref),
+        _ => Err(PikeError::Generic("Gmp.mpz is not a program.".to_string()))
+    }
+}
+
+// Returns a native int if the value fits, and a bignum otherwise.
+fn int_from_i128(i: i128, ctx: &PikeContext) -> Result<PikeThing, PikeError> {
+    match c_long::try_from(i) {
+        Ok(i) => Ok(PikeThing::Int(PikeInt::new(i))),
+        Err(_) => {
+            let digits = PikeString::from_str_slice(&i.to_string(), ctx);
+            Ok(PikeBignum::from_digits(digits, 10, ctx)?.into())
+        }
+    }
+}
+
+impl PikeThing {
+    /// Returns a Pike int from a sign (true if negative) and a big-endian
+    /// magnitude. The result is a native int if the value fits, and a bignum
+    /// otherwise.
+    pub fn from_int_bytes_be(negative: bool, magnitude: &[u8],
+        ctx: &PikeContext) -> Result<PikeThing, PikeError> {
+        let first_nonzero = magnitude.iter().position(|&b| b != 0)
+            .unwrap_or(magnitude.len());
+        let magnitude = &magnitude[first_nonzero..];
+        if magnitude.len() < 16 {
+            let abs = magnitude.iter()
+                .fold(0i128, |acc, &b| acc << 8 | i128::from(b));
+            return int_from_i128(if negative { -abs } else { abs }, ctx);
+        }
+        let digits = PikeString::from_vec_slice(magnitude, ctx);
+        let abs = PikeBignum::from_digits(digits, 256, ctx)?;
+        if negative {
+            abs.object.call_func("`*", vec![&PikeThing::from(-1)])
+        } else {
+            Ok(abs.into())
+        }
+    }
+
+    /// Returns the sign (true if negative) and the big-endian magnitude of a
+    /// Pike int, which may be a native int or a bignum.
+    pub fn to_int_bytes_be(&self, ctx: &PikeContext)
+    -> Result<(bool, Vec<u8>), PikeError> {
+        if let PikeThing::Int(ref i) = *self {
+            let abs = i128::from(i.integer).abs() as u128;
+            let bytes = abs.to_be_bytes();
+            let first_nonzero = bytes.iter().position(|&b| b != 0)
+                .unwrap_or(bytes.len() - 1);
+            return Ok((i.integer < 0, bytes[first_nonzero..].to_vec()));
+        }
+        match PikeBignum::from_thing(self, ctx) {
+            Some(bignum) => bignum.to_bytes_be(),
+            None => Err(PikeError::Args("Expected an int.".to_string()))
+        }
+    }
+}
+
+macro_rules! gen_from_type_wide_int {
+    ($inttype: ident) => {
+        impl<'ctx> TryFromWithCtx<'ctx, $inttype> for PikeThing {
+            fn try_from_with_ctx(i: $inttype, ctx: &'ctx PikeContext)
+            -> Result<Self, PikeError> {
+                match i128::try_from(i) {
+                    Ok(i) => int_from_i128(i, ctx),
+                    Err(_) => {
+                        let digits =
+                            PikeString::from_str_slice(&i.to_string(), ctx);
+                        PikeBignum::from_digits(digits, 10, ctx)
+                            .map(PikeThing::from)
+                    }
+                }
+            }
+        }
+    };
+}
+
+// Types that may overflow a native Pike int. Creating a bignum may fail, so
+// these are converted with TryFromWithCtx. The smaller types are converted
+// with From, see pike_thing.rs.
+gen_from_type_wide_int!(u64);
+gen_from_type_wide_int!(i128);
+gen_from_type_wide_int!(u128);
+gen_from_type_wide_int!(usize);
+gen_from_type_wide_int!(isize);
+
+macro_rules! gen_try_from_thing {
+    ($inttype: ident) => {
+        impl<'ctx> TryFromWithCtx<'ctx, PikeThing> for $inttype {
+            fn try_from_with_ctx(thing: PikeThing, ctx: &'ctx PikeContext)
+            -> Result<Self, PikeError> {
+                if let PikeThing::Int(ref i) = thing {
+                    return $inttype::try_from(i.integer).map_err(|_| {
+                        PikeError::Args(format!(
+                            "Integer {} is out of range for {}.",
+                            i.integer, stringify!($inttype)))
+                    });
+                }
+                match PikeBignum::from_thing(&thing, ctx) {
+                    Some(bignum) => bignum.to_integer(),
+                    None => Err(PikeError::Args("Expected an int.".to_string()))
+                }
+            }
+        }
+    };
+}
+
+gen_try_from_thing!(u128);
+gen_try_from_thing!(u64);
+gen_try_from_thing!(u32);
+gen_try_from_thing!(u16);
+gen_try_from_thing!(u8);
+gen_try_from_thing!(usize);
+
+gen_try_from_thing!(i128);
+gen_try_from_thing!(i64);
+gen_try_from_thing!(i32);
+gen_try_from_thing!(i16);
+gen_try_from_thing!(i8);
+gen_try_from_thing!(isize);
@@ -1,7 +1,27 @@
 use ::types::type_deps::*;
 
-use ::ffi::{svalue, apply_svalue, object};
+use ::ffi::{svalue, apply_svalue, object, find_identifier};
+use ::interpreter::prepare_error_message;
+use ::lazy_static::*;
 use std::os::raw::c_ushort;
+use std::sync::Mutex;
+use std::ffi::CString;
+
+type BoxedClosure = Box<Fn(&PikeContext, Vec<PikeThing>)
+    -> Result<PikeThing, PikeError> + Send>;
+
+// Storage of the hidden program that backs closures. The box is dropped when
+// the object is destructed.
+struct ClosureStorage {
+    closure: BoxedClosure
+}
+
+lazy_static! {
+    static ref CLOSURE_PROGRAM: Mutex<Option<PikeProgramRef<ClosureStorage>>> =
+        Mutex::new(None);
+}
+
+const CLOSURE_CALL_NAME: &str = "call";
 
 #[derive(Debug)]
 pub struct PikeFunctionRef {
@@ -65,3 +85,94 @@ impl<'ctx> PikeFunction<'ctx> {
         })
     }
 }
+
+impl<'ctx> PikeFunction<'ctx> {
+    /// Wraps a Rust closure as a Pike function, e.g. to pass as a callback to
+    /// Pike code. Errors returned from the closure, as well as panics, are
+    /// thrown as Pike errors.
+    pub fn from_closure<F>(ctx: &'ctx PikeContext, closure: F)
+    -> Result<Self, PikeError>
+    where F: Fn(&PikeContext, Vec<PikeThing>) -> Result<PikeThing, PikeError>
+        + Send + 'static {
+        let prog_ref = CLOSURE_PROGRAM.lock().unwrap().as_ref()
+            .map(|prog_ref| prog_ref.clone_with_ctx(ctx))
+            .ok_or_else(|| PikeError::Generic(
+                "Closure program is not initialized.".to_string()))?;
+        let prog: PikeProgram<ClosureStorage> = prog_ref.into_with_ctx(ctx);
+        let obj = prog.clone_object(ClosureStorage {
+            closure: Box::new(closure)
+        })?;
+        let call_name = CString::new(CLOSURE_CALL_NAME).unwrap();
+        let fun_idx = unsafe {
+            find_identifier(call_name.as_ptr(), prog.as_mut_ptr())
+        };
+        if fun_idx < 0 {
+            return Err(PikeError::Generic(
+                "Closure program has no call function.".to_string()));
+        }
+        let obj_ref = PikeObjectRef::from(obj);
+        unsafe {
+            Ok(PikeFunction::new(obj_ref.as_mut_ptr(), fun_idx as c_ushort,
+                ctx))
+        }
+    }
+
+    /// Creates the hidden program that backs closures. Called from the
+    /// generated pike_module_init().
+    #[doc(hidden)]
+    pub fn init_closure_program(ctx: &PikeContext) {
+        PikeProgram::<ClosureStorage>::start_new_program(ctx, file!(), line!());
+        PikeProgram::<()>::add_pike_func(ctx, CLOSURE_CALL_NAME,
+            "function(mixed...:mixed)", call_closure);
+        let prog = PikeProgram::<ClosureStorage>::finish_program(ctx);
+        *CLOSURE_PROGRAM.lock().unwrap() = Some((&prog).into());
+    }
+
+    /// Releases the hidden program that backs closures. Called from the
+    /// generated pike_module_exit().
+    #[doc(hidden)]
+    pub fn exit_closure_program(_ctx: &PikeContext) {
+        CLOSURE_PROGRAM.lock().unwrap().take();
+    }
+}
+
+// Calls the closure of the current object with the arguments on the Pike
+// stack. Errors are handled like in the wrappers generated by pike_export.
+unsafe extern "C" fn call_closure(args: i32) {
+    let ctx = PikeContext::assume_got_context();
+    let errmsg: Option<String> = {
+        let catch_res = ::std::panic::catch_unwind(||
+            -> Result<PikeThing, PikeError> {
+                let ctx = PikeContext::assume_got_context();
+                let args: Vec<PikeThing> = (0..args)
+                    .map(|i| ctx.get_from_stack((i - args) as isize))
+                    .collect();
+                let mut cur_pike_obj =
+                    PikeObject::<ClosureStorage>::current_object(&ctx);
+                let storage = cur_pike_obj.wrapped();
+                (storage.closure)(&ctx, args)
+            });
+
+        match catch_res {
+            Ok(ref inner_res) => {
+                match *inner_res {
+                    Ok(ref pt) => {
+                        ctx.push_to_stack(pt.clone_with_ctx(&ctx));
+                        None
+                    }
+                    Err(ref err) => {
+                        Some(format!("{}", &err))
+                    }
+                }
+            }
+            Err(err) => {
+                Some(format!("{:?}", &err))
+            }
+        }
+    };
+
+    if let Some(e) = errmsg {
+        prepare_error_message(&e);
+        ctx.pike_error()
+    }
+}
@@ -44,8 +44,8 @@ macro_rules! gen_from_type {
     };
 }
 
-// FIXME: Convert overflowing types to Pike bignums?
-//gen_from_type!(u64);
+// Types that may not fit in a native Pike int are converted through
+// PikeThing instead, which can represent bignums. See pike_bignum.rs.
 gen_from_type!(u32);
 gen_from_type!(u16);
 gen_from_type!(u8);
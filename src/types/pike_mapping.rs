@@ -1,7 +1,10 @@
 use ::types::type_deps::*;
 use ::serde::ser::*;
-use ::ffi::{mapping, really_free_mapping, debug_allocate_mapping,
-    mapping_insert, f_aggregate_mapping, f_get_iterator, svalue};
+use ::ffi::{mapping, mapping_data, keypair, really_free_mapping,
+    really_free_mapping_data, debug_allocate_mapping, mapping_insert,
+    f_aggregate_mapping, low_mapping_lookup, low_mapping_string_lookup,
+    map_delete_no_free, mapping_indices, mapping_values, svalue};
+use ::std::ptr::null_mut;
 
 #[derive(Debug)]
 pub struct PikeMappingRef {
@@ -43,13 +46,129 @@ impl<'ctx> PikeMapping<'ctx> {
     }
 
     pub fn insert (&self, key: PikeThing, val: PikeThing) {
-        let key_sval: svalue = key.into();
-        let val_sval: svalue = val.into();
+        let key_sval = key.borrowed_svalue(self.ctx);
+        let val_sval = val.borrowed_svalue(self.ctx);
         unsafe {
+            // mapping_insert() adds its own references to the key and value.
             mapping_insert (self.mapping_ref.ptr, &key_sval, &val_sval);
         }
     }
 
+    /// Returns the value for the specified key, if present.
+    pub fn get(&self, key: &PikeThing) -> Option<PikeThing> {
+        let key_sval = key.borrowed_svalue(self.ctx);
+        unsafe {
+            let val = low_mapping_lookup(self.mapping_ref.ptr, &key_sval);
+            val.as_ref().map(|val| PikeThing::from_svalue_ref(val, self.ctx))
+        }
+    }
+
+    /// Returns the value for the specified string key, if present.
+    pub fn get_str(&self, key: &str) -> Option<PikeThing> {
+        let key_str = PikeString::from_str_slice(key, self.ctx);
+        let key_ref: PikeStringRef = key_str.into();
+        unsafe {
+            let val = low_mapping_string_lookup(self.mapping_ref.ptr,
+                key_ref.as_mut_ptr());
+            val.as_ref().map(|val| PikeThing::from_svalue_ref(val, self.ctx))
+        }
+    }
+
+    /// Converts the value for the specified string key with `FromPike`. A
+    /// missing key is converted as `UNDEFINED`, so an error is only returned
+    /// for it if `T` isn't optional.
+    pub fn field<T>(&self, key: &str) -> Result<T, ::serialization::Error>
+    where T: FromPike {
+        match self.optional_field(key)? {
+            Some(val) => Ok(val),
+            None => T::from_pike(PikeThing::undefined(), self.ctx).map_err(|_| {
+                ::serialization::Error::new(format!("Missing field {}.", key))
+            })
+        }
+    }
+
+    /// Converts the value for the specified string key with `FromPike`, or
+    /// returns None if the key is missing or the value is `UNDEFINED`.
+    pub fn optional_field<T>(&self, key: &str)
+    -> Result<Option<T>, ::serialization::Error>
+    where T: FromPike {
+        match self.get_str(key) {
+            None | Some(PikeThing::Undefined) => Ok(None),
+            Some(val) => {
+                T::from_pike(val, self.ctx).map(Some).map_err(|e| e.at_key(key))
+            }
+        }
+    }
+
+    /// Converts the value with `ToPike` and inserts it for the specified
+    /// string key, unless it converts to `UNDEFINED`.
+    pub fn insert_field<T>(&self, key: &str, val: &T)
+    -> Result<(), ::serialization::Error>
+    where T: ?Sized + ToPike {
+        let val = val.to_pike(self.ctx).map_err(|e| e.at_key(key))?;
+        if let PikeThing::Undefined = val {
+            return Ok(());
+        }
+        self.insert(PikeString::from_str_slice(key, self.ctx).into(), val);
+        Ok(())
+    }
+
+    /// Returns true if the mapping contains the specified key.
+    pub fn contains_key(&self, key: &PikeThing) -> bool {
+        let key_sval = key.borrowed_svalue(self.ctx);
+        unsafe { !low_mapping_lookup(self.mapping_ref.ptr, &key_sval).is_null() }
+    }
+
+    /// Removes the specified key from the mapping and returns its value, if
+    /// it was present (like Pike's `m_delete()`).
+    pub fn remove(&self, key: &PikeThing) -> Option<PikeThing> {
+        if !self.contains_key(key) {
+            return None;
+        }
+        let mut key_sval = key.borrowed_svalue(self.ctx);
+        let mut removed = svalue::undefined();
+        unsafe {
+            // The reference held by the mapping is transferred to removed.
+            map_delete_no_free(self.mapping_ref.ptr, &mut key_sval,
+                &mut removed);
+        }
+        Some(removed.into())
+    }
+
+    /// Returns an array of the keys of the mapping. The order corresponds to
+    /// the order of values().
+    pub fn keys(&self) -> PikeArray<'ctx> {
+        let array_ref = unsafe {
+            PikeArrayRef::from_ptr(mapping_indices(self.mapping_ref.ptr))
+        };
+        array_ref.into_with_ctx(self.ctx)
+    }
+
+    /// Returns an array of the values of the mapping. The order corresponds
+    /// to the order of keys().
+    pub fn values(&self) -> PikeArray<'ctx> {
+        let array_ref = unsafe {
+            PikeArrayRef::from_ptr(mapping_values(self.mapping_ref.ptr))
+        };
+        array_ref.into_with_ctx(self.ctx)
+    }
+
+    /// Removes all entries from the mapping.
+    pub fn clear(&self) {
+        for key in self.keys() {
+            let mut key_sval = key.borrowed_svalue(self.ctx);
+            unsafe {
+                map_delete_no_free(self.mapping_ref.ptr, &mut key_sval,
+                    null_mut());
+            }
+        }
+    }
+
+    /// Returns the entry for the specified key, for in-place manipulation.
+    pub fn entry<'a>(&'a self, key: PikeThing) -> PikeMappingEntry<'a, 'ctx> {
+        PikeMappingEntry { mapping: self, key }
+    }
+
     pub fn aggregate_from_stack(
         num_entries: usize,
         ctx: &'ctx PikeContext) -> Self {
@@ -75,34 +194,117 @@ impl<'ctx> PikeMapping<'ctx> {
     }
 }
 
+impl<'ctx> Extend<(PikeThing, PikeThing)> for PikeMapping<'ctx> {
+    fn extend<I>(&mut self, iter: I)
+    where I: IntoIterator<Item = (PikeThing, PikeThing)> {
+        for (key, val) in iter {
+            self.insert(key, val);
+        }
+    }
+}
+
+/// An entry in a mapping, which may or may not be present. See
+/// `PikeMapping::entry()`.
+pub struct PikeMappingEntry<'a, 'ctx: 'a> {
+    mapping: &'a PikeMapping<'ctx>,
+    key: PikeThing
+}
+
+impl<'a, 'ctx> PikeMappingEntry<'a, 'ctx> {
+    pub fn key(&self) -> &PikeThing {
+        &self.key
+    }
+
+    /// Inserts the default value if the key is not present, and returns the
+    /// resulting value.
+    pub fn or_insert(self, default: PikeThing) -> PikeThing {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of the default function if the key is not present,
+    /// and returns the resulting value.
+    pub fn or_insert_with<F>(self, default: F) -> PikeThing
+    where F: FnOnce() -> PikeThing {
+        match self.mapping.get(&self.key) {
+            Some(val) => val,
+            None => {
+                let val = default();
+                let ctx = self.mapping.ctx;
+                self.mapping.insert(self.key, val.clone_with_ctx(ctx));
+                val
+            }
+        }
+    }
+
+    /// Replaces the value with the result of the function if the key is
+    /// present.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where F: FnOnce(PikeThing) -> PikeThing {
+        if let Some(val) = self.mapping.get(&self.key) {
+            let ctx = self.mapping.ctx;
+            self.mapping.insert(self.key.clone_with_ctx(ctx), f(val));
+        }
+        self
+    }
+}
+
+/// Iterates over the entries of a mapping by walking the hash buckets of its
+/// mapping data directly.
+///
+/// The iterator holds a reference to the mapping data and locks its values
+/// (like Pike's own mapping iterators do), so any modification of the mapping
+/// during iteration makes Pike copy the data first. The iterator thus always
+/// sees the entries the mapping had when iteration started.
 pub struct PikeMappingIterator<'ctx> {
-    iterator: PikeObject<'ctx, ()>
+    data: *mut mapping_data,
+    bucket: usize,
+    pair: *mut keypair,
+    ctx: &'ctx PikeContext
+}
+
+impl<'ctx> PikeMappingIterator<'ctx> {
+    fn new(mapping_ref: &PikeMappingRef, ctx: &'ctx PikeContext) -> Self {
+        let data = unsafe {
+            let data = (*mapping_ref.as_mut_ptr()).data;
+            (*data).refs += 1;
+            (*data).valrefs += 1;
+            data
+        };
+        PikeMappingIterator { data, bucket: 0, pair: null_mut(), ctx }
+    }
 }
 
 impl<'ctx> Iterator for PikeMappingIterator<'ctx> {
-  type Item = (PikeThing, PikeThing);
+    type Item = (PikeThing, PikeThing);
 
-  fn next(&mut self) -> Option<Self::Item> {
-    let ended = self.iterator.call_func("`!", vec![])
-        .expect("Error calling \"`!\" in iterator");
-    match ended {
-      PikeThing::Int(i) => {
-        if i.integer != 0 {
-          return None;
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            while self.pair.is_null() {
+                if self.bucket >= (*self.data).hashsize as usize {
+                    return None;
+                }
+                self.pair = *(*self.data).hash.as_mut_ptr().add(self.bucket);
+                self.bucket += 1;
+            }
+            let pair = &*self.pair;
+            self.pair = pair.next;
+            Some((PikeThing::from_svalue_ref(&pair.ind, self.ctx),
+                PikeThing::from_svalue_ref(&pair.val, self.ctx)))
         }
-      }
-      _ => panic!("Wrong type from iterator->`!")
     }
+}
 
-    let idx = self.iterator.call_func("index", vec![])
-        .expect("Error calling \"index\" in iterator");
-    let val = self.iterator.call_func("value", vec![])
-        .expect("Error calling \"value\" in iterator");
-    self.iterator.call_func("next", vec![])
-        .expect("Error calling \"next\" in iterator");;
-
-    Some((idx, val))
-  }
+impl<'ctx> Drop for PikeMappingIterator<'ctx> {
+    fn drop(&mut self) {
+        // The interpreter lock is held since we're borrowing a PikeContext.
+        unsafe {
+            (*self.data).valrefs -= 1;
+            (*self.data).refs -= 1;
+            if (*self.data).refs == 0 {
+                really_free_mapping_data(self.data);
+            }
+        }
+    }
 }
 
 impl<'ctx> IntoIterator for PikeMapping<'ctx> {
@@ -110,16 +312,7 @@ impl<'ctx> IntoIterator for PikeMapping<'ctx> {
     type IntoIter = PikeMappingIterator<'ctx>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Mapping(self.mapping_ref);
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeMappingIterator::<'ctx> { iterator: it.into_with_ctx(ctx) }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeMappingIterator::new(&self.mapping_ref, self.ctx)
     }
 }
 
@@ -128,16 +321,7 @@ impl<'a> IntoIterator for &'a PikeMapping<'a> {
     type IntoIter = PikeMappingIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Mapping(self.mapping_ref.clone_with_ctx(ctx));
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeMappingIterator { iterator: it.into_with_ctx(ctx) }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeMappingIterator::new(&self.mapping_ref, self.ctx)
     }
 }
 
@@ -145,10 +329,9 @@ impl<'a> Serialize for PikeMapping<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let mut map = serializer.serialize_map(Some(self.len()))?;
-        for (k, v) in self {
-            map.serialize_entry(&k.unwrap(self.ctx), &v.unwrap(self.ctx))?;
-        }
-        map.end()
+        // Serialized as a thing, to detect cycles in the contents.
+        let thing =
+            PikeThing::Mapping(self.mapping_ref.clone_with_ctx(self.ctx));
+        thing.unwrap(self.ctx).serialize(serializer)
     }
 }
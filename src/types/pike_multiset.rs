@@ -1,7 +1,12 @@
 use ::types::type_deps::*;
-use ::ffi::{multiset, multiset_sizeof, f_get_iterator, really_free_multiset,
-    svalue};
+use ::ffi::{multiset, msnode_ind, msnode_indval, multiset_sizeof,
+    really_free_multiset, allocate_multiset, multiset_insert, multiset_delete,
+    multiset_lookup, multiset_first, multiset_next, do_sub_msnode_ref, svalue,
+    MULTISET_INDVAL, MULTISET_FLAG_MASK, T_DELETED};
 use ::serde::ser::*;
+use ::serde::de::{Deserialize, Deserializer, Visitor, SeqAccess};
+use ::std::fmt;
+use ::std::ptr::null_mut;
 
 #[derive(Debug)]
 pub struct PikeMultisetRef {
@@ -35,6 +40,54 @@ pub struct PikeMultiset<'ctx> {
 define_from_impls!(PikeMultisetRef, PikeMultiset, Multiset, multiset_ref);
 
 impl<'ctx> PikeMultiset<'ctx> {
+    /// Returns an empty multiset.
+    pub fn new(ctx: &'ctx PikeContext) -> Self {
+        Self::with_capacity(0, ctx)
+    }
+
+    /// Returns an empty multiset with a pre-allocated capacity.
+    pub fn with_capacity(capacity: usize, ctx: &'ctx PikeContext) -> Self {
+        let multiset_ref = unsafe {
+            PikeMultisetRef::from_ptr(
+                allocate_multiset(capacity as i32, 0, null_mut()))
+        };
+        PikeMultiset { multiset_ref, ctx }
+    }
+
+    /// Returns a multiset containing the things produced by the iterator.
+    pub fn from_iter_with_ctx<I>(iter: I, ctx: &'ctx PikeContext) -> Self
+    where I: IntoIterator<Item = PikeThing> {
+        let iter = iter.into_iter();
+        let mut res = Self::with_capacity(iter.size_hint().0, ctx);
+        res.extend(iter);
+        res
+    }
+
+    /// Adds a thing to the multiset. Returns false if it was already present.
+    pub fn insert(&self, thing: PikeThing) -> bool {
+        if self.contains(&thing) {
+            return false;
+        }
+        let mut sval = thing.borrowed_svalue(self.ctx);
+        unsafe {
+            // multiset_insert() adds its own reference to the thing.
+            multiset_insert(self.multiset_ref.ptr, &mut sval);
+        }
+        true
+    }
+
+    /// Removes a thing from the multiset. Returns false if it wasn't present.
+    pub fn remove(&self, thing: &PikeThing) -> bool {
+        let mut sval = thing.borrowed_svalue(self.ctx);
+        unsafe { multiset_delete(self.multiset_ref.ptr, &mut sval) != 0 }
+    }
+
+    /// Returns true if the thing is present in the multiset.
+    pub fn contains(&self, thing: &PikeThing) -> bool {
+        let mut sval = thing.borrowed_svalue(self.ctx);
+        unsafe { !multiset_lookup(self.multiset_ref.ptr, &mut sval).is_null() }
+    }
+
     pub fn len(&self) -> usize {
         unsafe {
             multiset_sizeof(self.multiset_ref.ptr) as usize
@@ -46,31 +99,93 @@ impl<'ctx> PikeMultiset<'ctx> {
     }
 }
 
+impl<'ctx> Extend<PikeThing> for PikeMultiset<'ctx> {
+    fn extend<I>(&mut self, iter: I)
+    where I: IntoIterator<Item = PikeThing> {
+        for thing in iter {
+            self.insert(thing);
+        }
+    }
+}
+
+/// Iterates over the indices of a multiset by walking the nodes of its
+/// multiset data directly.
+///
+/// The iterator holds a node reference on the multiset (like Pike's own
+/// multiset iterators do), which keeps the nodes in place while iterating.
+/// Indices removed during iteration are skipped if they haven't been returned
+/// yet, and indices added during iteration may or may not be visited.
 pub struct PikeMultisetIterator<'ctx> {
-    iterator: PikeObject<'ctx, ()>
+    multiset_ref: PikeMultisetRef,
+    // The position of the next node, or None when iteration has ended and the
+    // node reference has been released.
+    nodepos: Option<isize>,
+    ctx: &'ctx PikeContext
+}
+
+impl<'ctx> PikeMultisetIterator<'ctx> {
+    fn new(multiset_ref: PikeMultisetRef, ctx: &'ctx PikeContext) -> Self {
+        // multiset_first() adds a node reference unless the multiset is empty.
+        let pos = unsafe { multiset_first(multiset_ref.as_mut_ptr()) };
+        let nodepos = if pos < 0 { None } else { Some(pos) };
+        PikeMultisetIterator { multiset_ref, nodepos, ctx }
+    }
+
+    // Equivalent of the OFF2MSNODE() and use_multiset_index() macros.
+    unsafe fn index_at(&self, pos: isize) -> svalue {
+        let msd = (*self.multiset_ref.as_mut_ptr()).msd;
+        let nodes = (*msd).nodes.as_mut_ptr();
+        // Both node types start with the same fields.
+        let node = if (*msd).flags as u32 & MULTISET_INDVAL != 0 {
+            (nodes as *mut msnode_indval).offset(pos) as *mut msnode_ind
+        } else {
+            (nodes as *mut msnode_ind).offset(pos)
+        };
+        let mut ind = ::std::ptr::read(&(*node).ind);
+        ind.tu.t.type_ &= !(MULTISET_FLAG_MASK as u16);
+        ind
+    }
 }
 
 impl<'ctx> Iterator for PikeMultisetIterator<'ctx> {
-    type Item = (PikeThing);
+    type Item = PikeThing;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ended = self.iterator.call_func("`!", vec![])
-            .expect("Error calling \"`!\" in iterator");
-        match ended {
-            PikeThing::Int(i) => {
-                if i.integer != 0 {
-                    return None;
+        loop {
+            let pos = self.nodepos?;
+            unsafe {
+                let ind = self.index_at(pos);
+                // The node reference keeps the node of an index that was
+                // removed after the previous call, marked as deleted.
+                let res = if ind.tu.t.type_ == T_DELETED as u16 {
+                    None
+                } else {
+                    Some(PikeThing::from_svalue_ref(&ind, self.ctx))
+                };
+                // multiset_next() releases the node reference at the end.
+                let next = multiset_next(self.multiset_ref.as_mut_ptr(), pos);
+                self.nodepos = if next < 0 { None } else { Some(next) };
+                if res.is_some() {
+                    return res;
                 }
             }
-            _ => panic!("Wrong type from iterator->`!")
         }
+    }
+}
 
-        let val = self.iterator.call_func("index", vec![])
-            .expect("Error calling \"index\" in iterator");
-        self.iterator.call_func("next", vec![])
-            .expect("Error calling \"next\" in iterator");
-
-        Some(val)
+impl<'ctx> Drop for PikeMultisetIterator<'ctx> {
+    fn drop(&mut self) {
+        if self.nodepos.is_some() {
+            // Equivalent of the sub_msnode_ref() macro. The interpreter lock
+            // is held since we're borrowing a PikeContext.
+            unsafe {
+                let ptr = self.multiset_ref.as_mut_ptr();
+                (*ptr).node_refs -= 1;
+                if (*ptr).node_refs == 0 && (*(*ptr).msd).refs == 1 {
+                    do_sub_msnode_ref(ptr);
+                }
+            }
+        }
     }
 }
 
@@ -79,16 +194,7 @@ impl<'ctx> IntoIterator for PikeMultiset<'ctx> {
     type IntoIter = PikeMultisetIterator<'ctx>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Multiset(self.multiset_ref);
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeMultisetIterator { iterator: it.into_with_ctx(ctx) }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeMultisetIterator::new(self.multiset_ref, self.ctx)
     }
 }
 
@@ -97,16 +203,8 @@ impl<'a> IntoIterator for &'a PikeMultiset<'a> {
     type IntoIter = PikeMultisetIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let ctx = self.ctx;
-        let thing = PikeThing::Multiset(self.multiset_ref.clone_with_ctx(ctx));
-        ctx.push_to_stack(thing);
-        unsafe { f_get_iterator(1); }
-        match ctx.pop_from_stack() {
-            PikeThing::Object(it) => {
-                PikeMultisetIterator { iterator: it.into_with_ctx(ctx) }
-            }
-            _ => panic!("Wrong type returned from f_get_iterator")
-        }
+        PikeMultisetIterator::new(self.multiset_ref.clone_with_ctx(self.ctx),
+            self.ctx)
     }
 }
 
@@ -114,10 +212,43 @@ impl<'a> Serialize for PikeMultiset<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let mut seq = serializer.serialize_seq(Some(self.len()))?;
-        for v in self {
-            seq.serialize_element(&v.unwrap(self.ctx))?;
+        // Serialized as a thing, to detect cycles in the contents.
+        let thing =
+            PikeThing::Multiset(self.multiset_ref.clone_with_ctx(self.ctx));
+        thing.unwrap(self.ctx).serialize(serializer)
+    }
+}
+struct PikeMultisetVisitor<'ctx> {
+    ctx: &'ctx PikeContext
+}
+
+impl<'de, 'ctx> Visitor<'de> for PikeMultisetVisitor<'ctx> {
+    type Value = PikeMultisetRef;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where A: SeqAccess<'de>,
+    {
+        let m =
+            PikeMultiset::with_capacity(seq.size_hint().unwrap_or(0), self.ctx);
+
+        // The elements are deserialized with the context that is already held.
+        let ctx = self.ctx;
+        while let Some(value) = seq.next_element_seed(PikeSeed::new(ctx))? {
+            m.insert(value);
         }
-        seq.end()
+        Ok(m.into())
+    }
+}
+
+impl<'de> Deserialize<'de> for PikeMultisetRef {
+    fn deserialize<D>(deserializer: D) -> Result<PikeMultisetRef, D::Error>
+    where D: Deserializer<'de> {
+        PikeContext::call_with_context(|ctx| {
+            deserializer.deserialize_seq(PikeMultisetVisitor { ctx: &ctx })
+        })
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,9 @@
 use ::types::type_deps::*;
 use ::ffi::{debug_master, Pike_interpreter_pointer, object,
-    schedule_really_free_object, svalue};
+    schedule_really_free_object, svalue, object_index_no_free,
+    object_set_index, object_indices, object_values, low_get_storage,
+    find_identifier};
+use ::std::ffi::CString;
 use ::std::marker::PhantomData;
 
 #[derive(Debug)]
@@ -85,27 +88,158 @@ impl<'ctx, TStorage> PikeObject<'ctx, TStorage> {
         })
     }
 
+    /// Returns the value of an identifier in this object, like Pike's `->`
+    /// operator. Returns UNDEFINED if there is no such identifier.
+    pub fn get(&self, name: &str) -> Result<PikeThing, PikeError> {
+        let name = PikeThing::from(PikeString::from_str_slice(name, self.ctx));
+        let mut index = arrow_index(&name, self.ctx);
+        let mut res = svalue::undefined();
+        self.ctx.catch_pike_error(|| {
+            unsafe {
+                object_index_no_free(&mut res, self.object_ref.as_mut_ptr(), 0,
+                    &mut index);
+            }
+        })?;
+        Ok(res.into())
+    }
+
+    /// Assigns a variable in this object, like Pike's `->` operator.
+    pub fn set(&self, name: &str, value: &PikeThing) -> Result<(), PikeError> {
+        let name = PikeThing::from(PikeString::from_str_slice(name, self.ctx));
+        let mut index = arrow_index(&name, self.ctx);
+        let mut from = value.borrowed_svalue(self.ctx);
+        self.ctx.catch_pike_error(|| {
+            unsafe {
+                object_set_index(self.object_ref.as_mut_ptr(), 0, &mut index,
+                    &mut from);
+            }
+        })
+    }
+
+    /// Returns the names of the identifiers in this object, like Pike's
+    /// `indices()`.
+    pub fn indices(&self) -> Result<PikeArray<'ctx>, PikeError> {
+        let array_ref = self.ctx.catch_pike_error(|| {
+            unsafe {
+                PikeArrayRef::from_ptr(
+                    object_indices(self.object_ref.as_mut_ptr(), 0))
+            }
+        })?;
+        Ok(array_ref.into_with_ctx(self.ctx))
+    }
+
+    /// Returns the values of the identifiers in this object, like Pike's
+    /// `values()`. The order corresponds to the order of indices().
+    pub fn values(&self) -> Result<PikeArray<'ctx>, PikeError> {
+        let array_ref = self.ctx.catch_pike_error(|| {
+            unsafe {
+                PikeArrayRef::from_ptr(
+                    object_values(self.object_ref.as_mut_ptr(), 0))
+            }
+        })?;
+        Ok(array_ref.into_with_ctx(self.ctx))
+    }
+
+    /// Returns the program of this object, or None if it has been destructed.
+    pub fn program(&self) -> Option<PikeProgram<'ctx, ()>> {
+        if self.is_destructed() {
+            return None;
+        }
+        unsafe {
+            let prog_ptr = (*self.object_ref.as_mut_ptr()).prog;
+            let prog_ref = PikeProgramRef::<()>::from_ptr_add_ref(prog_ptr,
+                self.ctx);
+            Some(prog_ref.into_with_ctx(self.ctx))
+        }
+    }
+
+    /// Returns true if this object is an instance of the program, or of a
+    /// program that inherits it.
+    pub fn is_instance_of<P>(&self, program: &PikeProgram<P>) -> bool {
+        if self.is_destructed() {
+            return false;
+        }
+        unsafe {
+            let prog_ptr = (*self.object_ref.as_mut_ptr()).prog;
+            low_get_storage(prog_ptr, program.as_mut_ptr()) != -1
+        }
+    }
+
+    /// Returns true if this object has an identifier with the specified
+    /// name.
+    pub fn has_identifier(&self, name: &str) -> bool {
+        if self.is_destructed() {
+            return false;
+        }
+        let cname = match CString::new(name) {
+            Ok(cname) => cname,
+            Err(_) => return false
+        };
+        unsafe {
+            let prog_ptr = (*self.object_ref.as_mut_ptr()).prog;
+            find_identifier(cname.as_ptr(), prog_ptr) != -1
+        }
+    }
+
+    /// Returns true if this and the other object are the same Pike object.
+    pub fn is_same_object<T>(&self, other: &PikeObject<T>) -> bool {
+        self.object_ref.as_mut_ptr() == other.object_ref.as_mut_ptr()
+    }
+
+    /// Returns true if this object has been destructed.
+    pub fn is_destructed(&self) -> bool {
+        unsafe { (*self.object_ref.as_mut_ptr()).prog.is_null() }
+    }
+}
+
+impl<'ctx> PikeObject<'ctx, ()> {
+    /// Returns this object with the storage type of the program, if it is an
+    /// instance of the program or of a program that inherits it.
+    pub fn downcast_to<T>(self, program: &PikeProgram<T>)
+    -> Option<PikeObject<'ctx, T>> {
+        if !self.is_instance_of(program) {
+            return None;
+        }
+        let object_ref = unsafe {
+            PikeObjectRef::<T>::from_ptr_add_ref(self.object_ref.as_mut_ptr(),
+                self.ctx)
+        };
+        Some(object_ref.into_with_ctx(self.ctx))
+    }
+
+    /// Returns this object with the storage type T, if it is an instance of
+    /// the program registered for T by `PikeProgram::finish_program()`, or of
+    /// a program that inherits it.
+    pub fn downcast<T: 'static>(self) -> Option<PikeObject<'ctx, T>> {
+        let prog_ref = PikeProgramRef::<T>::registered(self.ctx)?;
+        let program: PikeProgram<T> = prog_ref.into_with_ctx(self.ctx);
+        self.downcast_to(&program)
+    }
+}
+
+impl<'ctx, TStorage: 'static> PikeObject<'ctx, TStorage> {
     /// Returns a reference to the data contained by this Pike object.
-    // Pike ensures that object storage is aligned properly, so we'll ignore the
-    // clippy lint here.
-    #[allow(clippy::cast_ptr_alignment)]
     pub fn wrapped(&mut self) -> &mut TStorage {
         unsafe {
-            let ptr =
-                (*self.object_ref.as_mut_ptr()).storage as *const *mut TStorage;
+            let ptr = self.storage_ptr();
             assert!(!(*ptr).is_null());
             &mut **ptr
         }
     }
 
+    /// Returns a shared reference to the data contained by this Pike object.
+    pub fn wrapped_ref(&self) -> &TStorage {
+        unsafe {
+            let ptr = self.storage_ptr();
+            assert!(!(*ptr).is_null());
+            &**ptr
+        }
+    }
+
     /// Replaces the storage of this object.
-    // Pike ensures that object storage is aligned properly, so we'll ignore the
-    // clippy lint here.
-    #[allow(clippy::cast_ptr_alignment)]
     pub fn update_data(&self, data: TStorage) {
         unsafe {
-            let storage_ptr =
-                (*self.object_ref.as_mut_ptr()).storage as *mut *mut TStorage;
+            let storage_ptr = self.storage_ptr();
             if !(*storage_ptr).is_null() {
                 // Drop current Box
                 Box::from_raw(*storage_ptr);
@@ -113,4 +247,36 @@ impl<'ctx, TStorage> PikeObject<'ctx, TStorage> {
             *storage_ptr = Box::into_raw(Box::new(data));
         }
     }
+
+    // Returns a pointer to the storage of TStorage in this object. It is at
+    // an offset if the object's program inherits the program registered for
+    // TStorage. Panics if there is no such program, since the storage can't
+    // be located then.
+    // Pike ensures that object storage is aligned properly, so we'll ignore the
+    // clippy lint here.
+    #[allow(clippy::cast_ptr_alignment)]
+    fn storage_ptr(&self) -> *mut *mut TStorage {
+        assert!(!self.is_destructed(), "Object has been destructed");
+        unsafe {
+            let obj_ptr = self.object_ref.as_mut_ptr();
+            let offset = match PikeProgramRef::<TStorage>::registered(self.ctx) {
+                Some(prog_ref) => {
+                    low_get_storage((*obj_ptr).prog, prog_ref.as_mut_ptr())
+                },
+                None => panic!("No program is registered for the storage type")
+            };
+            assert!(offset != -1,
+                "Object is not an instance of the storage type's program");
+            (*obj_ptr).storage.offset(offset) as *mut *mut TStorage
+        }
+    }
+}
+
+// Returns an index svalue for an identifier name, which borrows the reference
+// held by the name. Strings with subtype 1 make Pike use the `-> lfun instead
+// of `[] when indexing objects.
+fn arrow_index(name: &PikeThing, ctx: &PikeContext) -> svalue {
+    let mut index = name.borrowed_svalue(ctx);
+    index.tu.t.subtype = 1;
+    index
 }
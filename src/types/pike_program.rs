@@ -1,7 +1,11 @@
 use ::types::type_deps::*;
 use ::ffi::*;
+use ::lazy_static::*;
 use std::ffi::CString;
+use ::std::any::TypeId;
+use ::std::collections::HashMap;
 use ::std::marker::PhantomData;
+use ::std::sync::Mutex;
 
 #[derive(Debug)]
 pub struct PikeProgramRef<TStorage>
@@ -12,6 +16,12 @@ where TStorage: Sized {
 
 refcounted_type_with_storage!(PikeProgramRef, program, DeferredProgramDrop);
 
+lazy_static! {
+    // The programs of the Rust storage types, registered by finish_program().
+    static ref PROGRAM_REGISTRY: Mutex<HashMap<TypeId, PikeProgramRef<()>>> =
+        Mutex::new(HashMap::new());
+}
+
 struct DeferredProgramDrop {
     ptr: *mut program
 }
@@ -28,6 +38,35 @@ impl DropWithContext for DeferredProgramDrop {
     }
 }
 
+impl<TStorage> PikeProgramRef<TStorage> {
+    /// Returns the id of the program, which identifies it in Pike types like
+    /// `object(is 4711)`.
+    pub fn id(&self) -> i32 {
+        unsafe { (*self.ptr).id }
+    }
+}
+
+impl<TStorage: 'static> PikeProgramRef<TStorage> {
+    /// Returns the program registered for the storage type by
+    /// `PikeProgram::finish_program()`, if any.
+    pub fn registered(ctx: &PikeContext) -> Option<Self> {
+        let registry = PROGRAM_REGISTRY.lock().unwrap();
+        registry.get(&TypeId::of::<TStorage>()).map(|prog_ref| {
+            unsafe { Self::from_ptr_add_ref(prog_ref.ptr, ctx) }
+        })
+    }
+
+    fn register(&self, ctx: &PikeContext) {
+        // Untyped programs have no storage to look up.
+        if TypeId::of::<TStorage>() == TypeId::of::<()>() {
+            return;
+        }
+        let prog_ref = self.clone_with_ctx(ctx).into_untyped();
+        PROGRAM_REGISTRY.lock().unwrap()
+            .insert(TypeId::of::<TStorage>(), prog_ref);
+    }
+}
+
 #[derive(Debug)]
 pub struct PikeProgram<'ctx, TStorage>
 where TStorage: Sized {
@@ -57,13 +96,21 @@ impl<'ctx, TStorage> PikeProgram<'ctx, TStorage> {
         Self { program_ref: program_ref, ctx: ctx }
     }
 
+    pub(crate) fn as_mut_ptr(&self) -> *mut program {
+        self.program_ref.ptr
+    }
+
     /// Instantiates a new program by finishing the current compilation unit.
-    pub fn finish_program(ctx: &'ctx PikeContext) -> Self {
+    /// The program is registered for the storage type, see
+    /// `PikeObject::downcast()`.
+    pub fn finish_program(ctx: &'ctx PikeContext) -> Self
+    where TStorage: 'static {
         let new_prog_ptr: *mut program;
         unsafe {
             new_prog_ptr = debug_end_program();
             let prog_ref =
                 PikeProgramRef::<TStorage>::from_ptr_add_ref(new_prog_ptr, ctx);
+            prog_ref.register(ctx);
             prog_ref.into_with_ctx(ctx)
         }
     }
@@ -87,6 +134,13 @@ impl<'ctx, TStorage> PikeProgram<'ctx, TStorage> {
         })
     }
 
+    /// Releases the programs registered by finish_program(). Called from the
+    /// generated pike_module_exit().
+    #[doc(hidden)]
+    pub fn clear_registry(_ctx: &PikeContext) {
+        PROGRAM_REGISTRY.lock().unwrap().clear();
+    }
+
     /// Returns the program that is currently being compiled.
     pub fn current_compilation(ctx: &'ctx PikeContext) -> Self {
         unsafe {
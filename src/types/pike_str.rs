@@ -1,7 +1,14 @@
 use ::types::type_deps::*;
-use ::ffi::{pike_string, f_string_to_utf8, f_utf8_to_string, really_free_string,
-    debug_make_shared_binary_string, svalue};
+use ::ffi::{pike_string, p_wchar1, p_wchar2, really_free_string,
+    debug_make_shared_binary_string, debug_make_shared_binary_string1,
+    debug_make_shared_binary_string2, string_slice, add_shared_strings,
+    svalue};
 use serde::ser::*;
+use ::std::cmp::Ordering;
+use ::std::convert::TryFrom;
+use ::std::hash::{Hash, Hasher};
+use ::types::range::resolve_range;
+use ::std::ops::RangeBounds;
 
 #[derive(Debug)]
 pub struct PikeStringRef {
@@ -34,63 +41,87 @@ pub struct PikeString<'ctx> {
 
 define_from_impls!(PikeStringRef, PikeString, PikeString, string_ref);
 
-impl<'ctx> From<PikeString<'ctx>> for String {
-    fn from(pikestr: PikeString) -> String {
-        let ctx = pikestr.ctx;
-        let pt = PikeThing::PikeString(pikestr.into());
-        ctx.push_to_stack(pt);
+// Pike strings are shared, i.e. there is only one instance of each distinct
+// string, so strings can be compared and hashed by pointer identity.
+impl PartialEq for PikeStringRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
 
-        unsafe {
-            f_string_to_utf8(1);
-            let thing = ctx.pop_from_stack();
-            match thing {
-                PikeThing::PikeString(res) => {
-                    let pikestr = res.ptr;
-                    // pike_string.str is strangely enough signed so we need to
-                    // transmute to get the type we need.
-                    let slice: &[i8] =
-                        ::std::slice::from_raw_parts(&((*pikestr).str[0]),
-                        (*pikestr).len as usize);
-                    let slice2: &[u8] = &*(slice as *const [i8] as *const [u8]);
-                    let mut v: ::std::vec::Vec<u8> = ::std::vec::Vec::new();
-                    v.extend_from_slice(slice2);
-                    String::from_utf8(v).unwrap()
-                }
-                _ => {
-                    panic!("string_to_utf8 returned wrong type");
-                }
-            }
-        }
+impl Eq for PikeStringRef {}
+
+impl Hash for PikeStringRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ptr.hash(state);
+    }
+}
+
+impl<'ctx> PartialEq for PikeString<'ctx> {
+    fn eq(&self, other: &Self) -> bool {
+        self.string_ref == other.string_ref
     }
 }
 
-impl<'a> From<&'a PikeString<'a>> for String {
-    fn from(pikestr: &'a PikeString) -> String {
-        let ctx = pikestr.ctx;
-        let pt = PikeThing::PikeString(pikestr.clone().into());
-        ctx.push_to_stack(pt);
+impl<'ctx> Eq for PikeString<'ctx> {}
 
-        unsafe {
-            f_string_to_utf8(1);
-            let thing = ctx.pop_from_stack();
-            match thing {
-                PikeThing::PikeString(res) => {
-                    let pikestr = res.ptr;
-                    // pike_string.str is strangely enough signed so we need to
-                    // transmute to get the type we need.
-                    let slice: &[i8] =
-                        ::std::slice::from_raw_parts(&((*pikestr).str[0]),
-                        (*pikestr).len as usize);
-                    let slice2: &[u8] = &*(slice as *const [i8] as *const [u8]);
-                    let mut v: ::std::vec::Vec<u8> = ::std::vec::Vec::new();
-                    v.extend_from_slice(slice2);
-                    ::std::string::String::from_utf8(v).unwrap()
-                }
-                _ => {
-                    panic!("string_to_utf8 returned wrong type");
-                }
-            }
+impl<'ctx> Hash for PikeString<'ctx> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.string_ref.hash(state);
+    }
+}
+
+impl<'ctx> PartialEq<str> for PikeString<'ctx> {
+    fn eq(&self, other: &str) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl<'a, 'ctx> PartialEq<&'a str> for PikeString<'ctx> {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+/// Strings are ordered by code point, like Pike's string comparison
+/// operators.
+impl<'ctx> Ord for PikeString<'ctx> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
         }
+        self.code_points().cmp(other.code_points())
+    }
+}
+
+impl<'ctx> PartialOrd for PikeString<'ctx> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Fails if the string contains code points that aren't valid Unicode scalar
+/// values. Use `PikeString::chars()` for a lossy conversion.
+impl<'ctx> TryFrom<PikeString<'ctx>> for String {
+    type Error = PikeError;
+
+    fn try_from(pikestr: PikeString) -> Result<String, PikeError> {
+        String::try_from(&pikestr)
+    }
+}
+
+impl<'a, 'ctx> TryFrom<&'a PikeString<'ctx>> for String {
+    type Error = PikeError;
+
+    fn try_from(pikestr: &'a PikeString<'ctx>) -> Result<String, PikeError> {
+        (0..pikestr.len()).map(|index| {
+            let code_point = unsafe { pikestr.code_point_at(index) };
+            ::std::char::from_u32(code_point as u32).ok_or_else(|| {
+                PikeError::Args(format!(
+                    "Invalid code point {} at index {} in string.",
+                    code_point, index))
+            })
+        }).collect()
     }
 }
 
@@ -103,47 +134,18 @@ impl<'ctx> FromWithCtx<'ctx, String> for PikeStringRef {
 
 impl<'ctx> FromWithCtx<'ctx, String> for PikeString<'ctx> {
     fn from_with_ctx(s: String, ctx: &'ctx PikeContext) -> Self {
-        let raw_str_ref = unsafe { PikeStringRef::from_ptr(
-            debug_make_shared_binary_string(
-                s.as_ptr() as *const i8,
-                s.len(),
-            )) };
-        let pt = PikeThing::PikeString(raw_str_ref);
-        ctx.push_to_stack(pt);
-
-        unsafe { f_utf8_to_string(1) };
-        match ctx.pop_from_stack() {
-            PikeThing::PikeString(str_ref) => {
-                PikeString { string_ref: str_ref, ctx: ctx }
-            }
-            _ => {
-                panic!("string_to_utf8 returned wrong type");
-            }
-        }
+        PikeString::from_str_slice(&s, ctx)
     }
 }
 
 impl<'ctx> PikeString<'ctx> {
 
+    /// Returns a Pike string with the same characters as the &str. The
+    /// resulting string is as narrow as its characters allow.
     pub fn from_str_slice(s: &str, ctx: &'ctx PikeContext) -> Self {
-        let raw_str_ref = unsafe { PikeStringRef::from_ptr(
-            debug_make_shared_binary_string(
-                s.as_ptr() as *const i8,
-                s.len(),
-            )) };
-
-        let pt = PikeThing::PikeString(raw_str_ref);
-        ctx.push_to_stack(pt);
-
-        unsafe { f_utf8_to_string(1) };
-        match ctx.pop_from_stack() {
-            PikeThing::PikeString(str_ref) => {
-                PikeString { string_ref: str_ref, ctx: ctx }
-            }
-            _ => {
-                panic!("string_to_utf8 returned wrong type");
-            }
-        }
+        let mut builder = PikeStringBuilder::with_capacity(s.len());
+        builder.push_str(s);
+        builder.finish(ctx)
     }
 
     pub fn from_vec(v: Vec<u8>, ctx: &'ctx PikeContext) -> Self {
@@ -165,6 +167,219 @@ impl<'ctx> PikeString<'ctx> {
             )) };
         PikeString { string_ref: str_ref, ctx: ctx }
     }
+
+    /// Returns the number of bits used to store each character, i.e. 8, 16
+    /// or 32.
+    pub fn width(&self) -> u32 {
+        8 << self.size_shift()
+    }
+
+    /// Returns the number of characters in the string.
+    pub fn len(&self) -> usize {
+        unsafe { (*self.string_ref.ptr).len as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the contents of an 8-bit wide string without copying it, or
+    /// None for wide strings.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        if self.size_shift() != 0 {
+            return None;
+        }
+        unsafe {
+            let data = (*self.string_ref.ptr).str.as_ptr() as *const u8;
+            Some(::std::slice::from_raw_parts(data, self.len()))
+        }
+    }
+
+    /// Returns an iterator over the characters of the string. Pike strings
+    /// may contain code points that aren't valid Unicode scalar values (such
+    /// as negative ones or surrogates); these are returned as
+    /// `std::char::REPLACEMENT_CHARACTER`.
+    pub fn chars<'a>(&'a self) -> PikeStringChars<'a, 'ctx> {
+        PikeStringChars { string: self, index: 0 }
+    }
+
+    /// Returns a new string with the characters in the specified range.
+    pub fn substring<R>(&self, range: R) -> Result<PikeString<'ctx>, PikeError>
+    where R: RangeBounds<usize> {
+        let (start, end) = resolve_range(&range, self.len(), "string")?;
+        let string_ref = unsafe {
+            PikeStringRef::from_ptr(string_slice(self.string_ref.ptr,
+                start as isize, (end - start) as isize))
+        };
+        Ok(PikeString { string_ref, ctx: self.ctx })
+    }
+
+    /// Returns the character index of the first occurrence of needle, if any.
+    pub fn find(&self, needle: &PikeString) -> Option<usize> {
+        let (len, needle_len) = (self.len(), needle.len());
+        if needle_len > len {
+            return None;
+        }
+        (0..=len - needle_len).find(|&start| {
+            (0..needle_len).all(|i| unsafe {
+                self.code_point_at(start + i) == needle.code_point_at(i)
+            })
+        })
+    }
+
+    /// Returns true if needle occurs in the string.
+    pub fn contains(&self, needle: &PikeString) -> bool {
+        self.find(needle).is_some()
+    }
+
+    /// Returns a new string consisting of this string followed by other.
+    pub fn concat(&self, other: &PikeString) -> PikeString<'ctx> {
+        let string_ref = unsafe {
+            PikeStringRef::from_ptr(add_shared_strings(self.string_ref.ptr,
+                other.string_ref.ptr))
+        };
+        PikeString { string_ref, ctx: self.ctx }
+    }
+
+    pub(crate) fn code_points<'a>(&'a self) -> impl Iterator<Item = i32> + 'a {
+        (0..self.len()).map(move |i| unsafe { self.code_point_at(i) })
+    }
+
+    fn size_shift(&self) -> u8 {
+        unsafe { (*self.string_ref.ptr).size_shift }
+    }
+
+    // Returns the code point at the specified index, which must be less than
+    // self.len().
+    pub(crate) unsafe fn code_point_at(&self, index: usize) -> i32 {
+        let data = (*self.string_ref.ptr).str.as_ptr();
+        match self.size_shift() {
+            0 => i32::from(*(data as *const u8).add(index)),
+            1 => i32::from(*(data as *const p_wchar1).add(index)),
+            _ => *(data as *const p_wchar2).add(index) as i32
+        }
+    }
+}
+
+/// Iterator over the characters of a PikeString. See `PikeString::chars()`.
+pub struct PikeStringChars<'a, 'ctx: 'a> {
+    string: &'a PikeString<'ctx>,
+    index: usize
+}
+
+impl<'a, 'ctx> Iterator for PikeStringChars<'a, 'ctx> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.index >= self.string.len() {
+            return None;
+        }
+        let code_point = unsafe { self.string.code_point_at(self.index) };
+        self.index += 1;
+        Some(::std::char::from_u32(code_point as u32)
+            .unwrap_or(::std::char::REPLACEMENT_CHARACTER))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.string.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+enum StringBuffer {
+    Narrow(Vec<u8>),
+    Wide16(Vec<p_wchar1>),
+    Wide32(Vec<p_wchar2>)
+}
+
+/// Builds a Pike string from Rust characters. The buffer starts out 8 bits
+/// wide and is widened as needed, so the resulting string is no wider than
+/// its characters require.
+pub struct PikeStringBuilder {
+    buf: StringBuffer
+}
+
+impl Default for PikeStringBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PikeStringBuilder {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Returns a builder with room for the specified number of characters
+    /// before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        PikeStringBuilder {
+            buf: StringBuffer::Narrow(Vec::with_capacity(capacity))
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_code_point(c as i32);
+    }
+
+    // Pushes any Pike character, including negative ones and surrogates.
+    pub(crate) fn push_code_point(&mut self, code_point: i32) {
+        if !(0..=0xffff).contains(&code_point) {
+            self.widen(2);
+        } else if code_point > 0xff {
+            self.widen(1);
+        }
+        match self.buf {
+            StringBuffer::Narrow(ref mut v) => v.push(code_point as u8),
+            StringBuffer::Wide16(ref mut v) => v.push(code_point as p_wchar1),
+            StringBuffer::Wide32(ref mut v) => v.push(code_point as p_wchar2)
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        if let StringBuffer::Narrow(ref mut v) = self.buf {
+            if s.is_ascii() {
+                v.extend_from_slice(s.as_bytes());
+                return;
+            }
+        }
+        for c in s.chars() {
+            self.push(c);
+        }
+    }
+
+    /// Creates the shared Pike string.
+    pub fn finish<'ctx>(self, ctx: &'ctx PikeContext) -> PikeString<'ctx> {
+        let str_ptr = unsafe {
+            match self.buf {
+                StringBuffer::Narrow(ref v) =>
+                    debug_make_shared_binary_string(v.as_ptr() as *const i8,
+                        v.len()),
+                StringBuffer::Wide16(ref v) =>
+                    debug_make_shared_binary_string1(v.as_ptr(), v.len()),
+                StringBuffer::Wide32(ref v) =>
+                    debug_make_shared_binary_string2(v.as_ptr(), v.len())
+            }
+        };
+        let string_ref = unsafe { PikeStringRef::from_ptr(str_ptr) };
+        PikeString { string_ref, ctx }
+    }
+
+    fn widen(&mut self, size_shift: u8) {
+        let new_buf = match self.buf {
+            StringBuffer::Narrow(ref v) if size_shift == 1 =>
+                StringBuffer::Wide16(
+                    v.iter().map(|&c| p_wchar1::from(c)).collect()),
+            StringBuffer::Narrow(ref v) =>
+                StringBuffer::Wide32(
+                    v.iter().map(|&c| c as p_wchar2).collect()),
+            StringBuffer::Wide16(ref v) if size_shift == 2 =>
+                StringBuffer::Wide32(
+                    v.iter().map(|&c| c as p_wchar2).collect()),
+            _ => return
+        };
+        self.buf = new_buf;
+    }
 }
 
 impl<'a> Serialize for PikeString<'a> {
@@ -172,7 +387,7 @@ impl<'a> Serialize for PikeString<'a> {
     where
         S: Serializer,
     {
-        let s: String = self.into();
+        let s = String::try_from(self).map_err(S::Error::custom)?;
         serializer.serialize_str(&s)
     }
 }
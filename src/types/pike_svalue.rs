@@ -79,6 +79,17 @@ impl From<svalue> for PikeThing {
     }
 }
 
+impl PikeThing {
+    /// Returns an svalue that borrows the reference held by this PikeThing,
+    /// for passing to Pike functions that don't take over a reference. The
+    /// svalue must not outlive the PikeThing.
+    pub(crate) fn borrowed_svalue(&self, ctx: &PikeContext) -> svalue {
+        let mut sval: svalue = self.clone_with_ctx(ctx).into();
+        sval.sub_ref(ctx);
+        sval
+    }
+}
+
 impl From<PikeThing> for svalue {
     fn from (pike_thing: PikeThing) -> Self {
         let mut u = ::ffi::anything { integer: 0 };
@@ -203,7 +214,7 @@ impl svalue {
         }
     }
 
-    fn type_(&self) -> u16 {
+    pub(crate) fn type_(&self) -> u16 {
         unsafe {
             self.tu.t.type_
         }
@@ -1,11 +1,17 @@
 use ::types::type_deps::*;
 use ::serde::ser::*;
 use ::serde::*;
-use ::ffi::svalue;
+use ::ffi::{svalue, apply_svalue};
 
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
+use std::rc::Rc;
 
-use serde::de::{Visitor, MapAccess, SeqAccess};
+use serde::de::{self, Visitor, MapAccess, SeqAccess, EnumAccess,
+    VariantAccess, DeserializeSeed};
+use ::serialization::{Options, bool_value, none_value, unit_variant,
+    wrap_variant};
 
 impl<'ctx, T, U> IntoWithCtx<'ctx, U> for T where U: FromWithCtx<'ctx, T>
 {
@@ -21,6 +27,14 @@ impl<'ctx, T, U> FromWithCtx<'ctx, T> for U where U: From<T>
     }
 }
 
+impl<'ctx, T, U> TryFromWithCtx<'ctx, T> for U where U: From<T>
+{
+    fn try_from_with_ctx(val: T, _ctx: &'ctx PikeContext)
+    -> Result<Self, PikeError> {
+        Ok(Self::from(val))
+    }
+}
+
 /// The `PikeThing` type. Equivalent to Pike's `svalue` type, with Rust idioms.
 #[derive(Debug)]
 pub enum PikeThing {
@@ -37,10 +51,50 @@ pub enum PikeThing {
     Undefined
 }
 
+/// A Pike thing with a context, which can be serialized with serde.
 #[derive(Debug)]
 pub struct PikeThingWithCtx<'ctx> {
     thing: PikeThing,
-    ctx: &'ctx PikeContext
+    ctx: &'ctx PikeContext,
+    guard: Rc<SerializeGuard>
+}
+
+/// Options for serializing Pike things with serde.
+#[derive(Debug, Clone)]
+pub struct SerializeOptions {
+    /// What to do when a container contains itself.
+    pub on_cycle: CyclePolicy,
+    /// The maximum nesting depth of containers, or None for no limit.
+    pub max_depth: Option<usize>
+}
+
+impl Default for SerializeOptions {
+    fn default() -> Self {
+        SerializeOptions {
+            on_cycle: CyclePolicy::Error,
+            max_depth: Some(128)
+        }
+    }
+}
+
+/// What to serialize in place of a container that is already being
+/// serialized, i.e. one that contains itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CyclePolicy {
+    /// Fail with a serde error.
+    Error,
+    /// Serialize a unit, e.g. null in JSON.
+    Unit,
+    /// Serialize the string.
+    Placeholder(String)
+}
+
+// Keeps track of the containers currently being serialized. It is shared by
+// all the PikeThingWithCtx of a serialization.
+#[derive(Debug)]
+struct SerializeGuard {
+    options: SerializeOptions,
+    visiting: RefCell<HashSet<usize>>
 }
 
 impl PikeThing {
@@ -72,8 +126,88 @@ impl PikeThing {
         }
     }
 
+    /// Returns the name of the thing's Pike type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PikeThing::Array(_) => "array",
+            PikeThing::Float(_) => "float",
+            PikeThing::Function(_) => "function",
+            PikeThing::Int(_) => "int",
+            PikeThing::Mapping(_) => "mapping",
+            PikeThing::Multiset(_) => "multiset",
+            PikeThing::Object(_) => "object",
+            PikeThing::PikeString(_) => "string",
+            PikeThing::Program(_) => "program",
+            PikeThing::Type(_) => "type",
+            PikeThing::Undefined => "UNDEFINED"
+        }
+    }
+
     pub fn unwrap(self, ctx: &PikeContext) -> PikeThingWithCtx {
-        PikeThingWithCtx { thing: self, ctx: ctx }
+        self.unwrap_with_options(SerializeOptions::default(), ctx)
+    }
+
+    /// Like `unwrap()`, with options for serializing cyclic and deeply nested
+    /// data.
+    pub fn unwrap_with_options<'ctx>(self, options: SerializeOptions,
+        ctx: &'ctx PikeContext) -> PikeThingWithCtx<'ctx> {
+        let guard = SerializeGuard {
+            options,
+            visiting: RefCell::new(HashSet::new())
+        };
+        PikeThingWithCtx { thing: self, ctx, guard: Rc::new(guard) }
+    }
+
+    /// Calls the thing, which should be a function, a program (which is then
+    /// cloned) or an object with a `()` method.
+    pub fn call(&self, args: Vec<PikeThing>, ctx: &PikeContext)
+    -> Result<PikeThing, PikeError> {
+        let num_args = args.len() as i32;
+        for a in args {
+            ctx.push_to_stack(a);
+        }
+        let mut func = self.borrowed_svalue(ctx);
+        ctx.catch_pike_error(|| {
+            unsafe {
+                apply_svalue(&mut func, num_args);
+            }
+            ctx.pop_from_stack()
+        })
+    }
+}
+
+impl<'ctx> PikeThingWithCtx<'ctx> {
+    fn child(&self, thing: PikeThing) -> PikeThingWithCtx<'ctx> {
+        PikeThingWithCtx { thing, ctx: self.ctx, guard: self.guard.clone() }
+    }
+
+    // Serializes a container with the closure, unless that would recurse
+    // infinitely or too deep.
+    fn serialize_container<S, F>(&self, ptr: usize, serializer: S, f: F)
+    -> Result<S::Ok, S::Error>
+    where S: ::serde::Serializer, F: FnOnce(S) -> Result<S::Ok, S::Error> {
+        let depth = {
+            let visiting = self.guard.visiting.borrow();
+            if visiting.contains(&ptr) {
+                return match self.guard.options.on_cycle {
+                    CyclePolicy::Error =>
+                        Err(ser::Error::custom("Cyclic data structure")),
+                    CyclePolicy::Unit => serializer.serialize_unit(),
+                    CyclePolicy::Placeholder(ref s) => serializer.serialize_str(s)
+                };
+            }
+            visiting.len()
+        };
+        if let Some(max_depth) = self.guard.options.max_depth {
+            if depth >= max_depth {
+                return Err(ser::Error::custom(
+                    format!("Maximum depth of {} exceeded", max_depth)));
+            }
+        }
+        self.guard.visiting.borrow_mut().insert(ptr);
+        let res = f(serializer);
+        self.guard.visiting.borrow_mut().remove(&ptr);
+        res
     }
 }
 
@@ -83,16 +217,40 @@ impl<'ctx> Serialize for PikeThingWithCtx<'ctx> {
         let ctx = self.ctx;
         match self.thing {
             PikeThing::Array(ref a_ref) => {
-                let a: PikeArray = a_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
-                a.serialize(serializer)
+                let ptr = a_ref.as_mut_ptr() as usize;
+                self.serialize_container(ptr, serializer, |serializer| {
+                    let a: PikeArray =
+                        a_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                    let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                    for v in &a {
+                        seq.serialize_element(&self.child(v))?;
+                    }
+                    seq.end()
+                })
             }
             PikeThing::Mapping(ref m_ref) => {
-                let m: PikeMapping = m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
-                m.serialize(serializer)
+                let ptr = m_ref.as_mut_ptr() as usize;
+                self.serialize_container(ptr, serializer, |serializer| {
+                    let m: PikeMapping =
+                        m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                    let mut map = serializer.serialize_map(Some(m.len()))?;
+                    for (k, v) in &m {
+                        map.serialize_entry(&self.child(k), &self.child(v))?;
+                    }
+                    map.end()
+                })
             }
             PikeThing::Multiset(ref m_ref) => {
-                let m: PikeMultiset = m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
-                m.serialize(serializer)
+                let ptr = m_ref.as_mut_ptr() as usize;
+                self.serialize_container(ptr, serializer, |serializer| {
+                    let m: PikeMultiset =
+                        m_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
+                    let mut seq = serializer.serialize_seq(Some(m.len()))?;
+                    for v in &m {
+                        seq.serialize_element(&self.child(v))?;
+                    }
+                    seq.end()
+                })
             }
             PikeThing::PikeString(ref s_ref) => {
                 let s: PikeString = s_ref.clone_with_ctx(ctx).into_with_ctx(ctx);
@@ -109,17 +267,34 @@ impl<'ctx> Serialize for PikeThingWithCtx<'ctx> {
   }
 }
 
-struct PikeThingVisitor<'ctx> {
+// Creates Pike things from any serde data. Nested values are deserialized by
+// the visitor itself, acting as a DeserializeSeed, so that they share the
+// context and options.
+#[derive(Clone, Copy)]
+struct PikeThingVisitor<'a, 'ctx> {
+    options: &'a Options,
     ctx: &'ctx PikeContext
 }
 
-impl<'de, 'ctx> Visitor<'de> for PikeThingVisitor<'ctx> {
+impl<'a, 'ctx> PikeThingVisitor<'a, 'ctx> {
+    fn wide_int<T, E>(&self, value: T) -> Result<PikeThing, E>
+    where PikeThing: TryFromWithCtx<'ctx, T>, E: de::Error {
+        PikeThing::try_from_with_ctx(value, self.ctx).map_err(E::custom)
+    }
+}
+
+impl<'de, 'a, 'ctx> Visitor<'de> for PikeThingVisitor<'a, 'ctx> {
     type Value = PikeThing;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_str("something serializeable to a Pike thing")
     }
 
+    fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+    where E: de::Error {
+        bool_value(value, self.options, self.ctx).map_err(E::custom)
+    }
+
     fn visit_i8<E>(self, value: i8) -> Result<Self::Value, E> {
         Ok(value.into())
     }
@@ -136,11 +311,88 @@ impl<'de, 'ctx> Visitor<'de> for PikeThingVisitor<'ctx> {
         Ok(value.into())
     }
 
+    fn visit_i128<E>(self, value: i128) -> Result<Self::Value, E>
+    where E: de::Error {
+        self.wide_int(value)
+    }
+
+    fn visit_u8<E>(self, value: u8) -> Result<Self::Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u16<E>(self, value: u16) -> Result<Self::Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u32<E>(self, value: u32) -> Result<Self::Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where E: de::Error {
+        self.wide_int(value)
+    }
+
+    fn visit_u128<E>(self, value: u128) -> Result<Self::Value, E>
+    where E: de::Error {
+        self.wide_int(value)
+    }
+
+    fn visit_f32<E>(self, value: f32) -> Result<Self::Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_char<E>(self, value: char) -> Result<Self::Value, E> {
+        let mut builder = PikeStringBuilder::with_capacity(1);
+        builder.push(value);
+        Ok(PikeThing::PikeString(builder.finish(self.ctx).into()))
+    }
+
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
         let pike_str = PikeString::from_str_slice(v, self.ctx);
         Ok(PikeThing::PikeString(pike_str.into()))
     }
 
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where E: de::Error {
+        self.visit_str(&v)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        let pike_str = PikeString::from_vec_slice(v, self.ctx);
+        Ok(PikeThing::PikeString(pike_str.into()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        let pike_str = PikeString::from_vec(v, self.ctx);
+        Ok(PikeThing::PikeString(pike_str.into()))
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where E: de::Error {
+        none_value(self.options, self.ctx).map_err(E::custom)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where D: Deserializer<'de> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where E: de::Error {
+        none_value(self.options, self.ctx).map_err(E::custom)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D)
+    -> Result<Self::Value, D::Error>
+    where D: Deserializer<'de> {
+        deserializer.deserialize_any(self)
+    }
+
     fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
     where M: MapAccess<'de>,
     {
@@ -149,7 +401,7 @@ impl<'de, 'ctx> Visitor<'de> for PikeThingVisitor<'ctx> {
 
         // While there are entries remaining in the input, add them
         // into our map.
-        while let Some((key, value)) = access.next_entry()? {
+        while let Some((key, value)) = access.next_entry_seed(self, self)? {
             m.insert(key, value);
         }
 
@@ -162,18 +414,107 @@ impl<'de, 'ctx> Visitor<'de> for PikeThingVisitor<'ctx> {
         let mut a =
             PikeArray::with_capacity(seq.size_hint().unwrap_or(0), self.ctx);
 
-        while let Some(value) = seq.next_element()? {
+        while let Some(value) = seq.next_element_seed(self)? {
             a.append(value)
         }
         Ok(PikeThing::Array(a.into()))
     }
+
+    // The shape of the variant isn't known here, so the content is read as an
+    // optional newtype variant. Variants without content are represented as
+    // unit variants, and the others according to the options.
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where A: EnumAccess<'de> {
+        let (variant, access): (String, _) = data.variant()?;
+        match access.newtype_variant_seed(VariantContent(self))? {
+            Some(content) => {
+                wrap_variant(&variant, content, self.options, self.ctx)
+            },
+            None => unit_variant(&variant, self.options, self.ctx)
+        }.map_err(de::Error::custom)
+    }
+}
+
+// Reads the content of an enum variant, which is None for a unit or a missing
+// value.
+struct VariantContent<'a, 'ctx: 'a>(PikeThingVisitor<'a, 'ctx>);
+
+impl<'de, 'a, 'ctx> DeserializeSeed<'de> for VariantContent<'a, 'ctx> {
+    type Value = Option<PikeThing>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where D: Deserializer<'de> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+impl<'de, 'a, 'ctx> Visitor<'de> for VariantContent<'a, 'ctx> {
+    type Value = Option<PikeThing>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the content of an enum variant")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where D: Deserializer<'de> {
+        self.0.deserialize(deserializer).map(Some)
+    }
+}
+
+impl<'de, 'a, 'ctx> DeserializeSeed<'de> for PikeThingVisitor<'a, 'ctx> {
+    type Value = PikeThing;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<PikeThing, D::Error>
+    where D: Deserializer<'de> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+/// Deserializes a Pike thing using the caller's context, e.g.
+/// `PikeSeed::new(&ctx).deserialize(&mut deserializer)`. Unlike the
+/// `Deserialize` impl, this doesn't acquire the interpreter lock, so it can be
+/// used where a context is already held, such as in exported functions.
+#[derive(Debug, Clone)]
+pub struct PikeSeed<'ctx> {
+    options: Options,
+    ctx: &'ctx PikeContext
+}
+
+impl<'ctx> PikeSeed<'ctx> {
+    pub fn new(ctx: &'ctx PikeContext) -> Self {
+        Self::with_options(Options::default(), ctx)
+    }
+
+    /// Creates a seed with options for how bools, None and enums are
+    /// represented.
+    pub fn with_options(options: Options, ctx: &'ctx PikeContext) -> Self {
+        PikeSeed { options, ctx }
+    }
+}
+
+impl<'de, 'ctx> DeserializeSeed<'de> for PikeSeed<'ctx> {
+    type Value = PikeThing;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<PikeThing, D::Error>
+    where D: Deserializer<'de> {
+        let visitor = PikeThingVisitor { options: &self.options, ctx: self.ctx };
+        deserializer.deserialize_any(visitor)
+    }
 }
 
 impl<'de> Deserialize<'de> for PikeThing {
     fn deserialize<D>(deserializer: D) -> Result<PikeThing, D::Error>
     where D: Deserializer<'de> {
         PikeContext::call_with_context(|ctx| {
-            deserializer.deserialize_any(PikeThingVisitor { ctx: &ctx })
+            PikeSeed::new(&ctx).deserialize(deserializer)
         })
     }
 }
@@ -194,8 +535,8 @@ macro_rules! gen_from_type_int {
     };
 }
 
-// FIXME: Convert overflowing types to Pike bignums?
-//gen_from_type_int!(u64);
+// Conversions from u64, i128 and u128, which may overflow into bignums, are
+// implemented in pike_bignum.rs.
 gen_from_type_int!(u32);
 gen_from_type_int!(u16);
 gen_from_type_int!(u8);
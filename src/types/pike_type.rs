@@ -1,5 +1,12 @@
 use ::types::type_deps::*;
-use ::ffi::{pike_type, really_free_pike_type};
+use ::ffi::{pike_type, really_free_pike_type, pike_types_le, match_types,
+    get_type_of_svalue, parse_type, PIKE_T_INT, PIKE_T_FLOAT,
+    PIKE_T_STRING, PIKE_T_ARRAY, PIKE_T_MAPPING, PIKE_T_MULTISET,
+    PIKE_T_FUNCTION, PIKE_T_OBJECT, PIKE_T_PROGRAM, PIKE_T_TYPE, PIKE_T_ZERO,
+    PIKE_T_NAME, PIKE_T_ATTRIBUTE, PIKE_T_SCOPE, T_VOID, T_MANY, T_ASSIGN,
+    T_NOT, T_AND, T_OR};
+use ::std::fmt;
+use ::std::ffi::CString;
 
 #[derive(Debug)]
 pub struct PikeTypeRef {
@@ -23,3 +30,440 @@ impl DropWithContext for DeferredTypeDrop {
         }
     }
 }
+
+impl PikeTypeRef {
+    /// Returns the type of a value, like Pike's `typeof()`.
+    pub fn of(thing: &PikeThing, ctx: &PikeContext) -> Self {
+        let sval = thing.borrowed_svalue(ctx);
+        unsafe {
+            PikeTypeRef::from_ptr(get_type_of_svalue(&sval))
+        }
+    }
+
+    /// Returns true if every value of this type is also a value of the other
+    /// type.
+    pub fn is_subtype_of(&self, other: &PikeTypeRef, _ctx: &PikeContext)
+    -> bool {
+        unsafe { pike_types_le(self.ptr, other.ptr) != 0 }
+    }
+
+    /// Returns true if some value of this type is also a value of the other
+    /// type, i.e. if Pike's compiler would accept passing one where the other
+    /// is expected.
+    pub fn matches(&self, other: &PikeTypeRef, _ctx: &PikeContext) -> bool {
+        unsafe { match_types(self.ptr, other.ptr) != 0 }
+    }
+}
+
+impl<'a, 'ctx> TryFromWithCtx<'ctx, &'a PikeType> for PikeTypeRef {
+    /// Creates a type through Pike's type parser. Named object and program
+    /// types are resolved through the master object first, and fail if the
+    /// name doesn't refer to a program.
+    fn try_from_with_ctx(t: &'a PikeType, ctx: &'ctx PikeContext)
+    -> Result<Self, PikeError> {
+        let type_str = CString::new(t.resolve_names(ctx)?.to_string())
+            .map_err(|_| PikeError::Args("Invalid type string.".to_string()))?;
+        ctx.catch_pike_error(|| {
+            unsafe { PikeTypeRef::from_ptr(parse_type(type_str.as_ptr())) }
+        })
+    }
+}
+
+/// A Pike type in structured form. It can be created from a `PikeTypeRef`, or
+/// parsed from a type string such as "function(int, string|void : array(int))".
+#[derive(Debug, Clone, PartialEq)]
+pub enum PikeType {
+    Int(IntRange),
+    Float,
+    /// A string with characters in the specified range.
+    String(IntRange),
+    Array(Box<PikeType>),
+    Mapping(Box<PikeType>, Box<PikeType>),
+    Multiset(Box<PikeType>),
+    Function(FunctionType),
+    Object(ObjectType),
+    /// A program whose instances are of the specified object type.
+    Program(ObjectType),
+    Type(Box<PikeType>),
+    Or(Vec<PikeType>),
+    And(Vec<PikeType>),
+    Not(Box<PikeType>),
+    Mixed,
+    Void,
+    Zero
+}
+
+/// An inclusive range of integers. A missing bound means that the range is
+/// unbounded in that direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntRange {
+    pub min: Option<i32>,
+    pub max: Option<i32>
+}
+
+/// The signature of a Pike function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionType {
+    pub args: Vec<PikeType>,
+    /// The type of any remaining arguments, i.e. `mixed ...`.
+    pub rest: Option<Box<PikeType>>,
+    pub ret: Box<PikeType>
+}
+
+/// The kind of objects that an object or program type refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectType {
+    Any,
+    /// Instances of the program with the specified id, or of programs that
+    /// inherit it.
+    Is(i32),
+    /// Instances of programs that implement the interface of the program with
+    /// the specified id.
+    Implements(i32),
+    /// Instances of a program referred to by name, e.g. "Stdio.File". Only
+    /// created by the type string parser, since Pike resolves names when
+    /// compiling.
+    Named(String)
+}
+
+impl IntRange {
+    pub fn new(min: Option<i32>, max: Option<i32>) -> Self {
+        IntRange { min, max }
+    }
+
+    /// Returns the range of all integers.
+    pub fn full() -> Self {
+        IntRange { min: None, max: None }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    // Pike uses the 32 bit limits to represent unbounded ranges.
+    fn from_bounds(min: i32, max: i32) -> Self {
+        IntRange {
+            min: if min == ::std::i32::MIN { None } else { Some(min) },
+            max: if max == ::std::i32::MAX { None } else { Some(max) }
+        }
+    }
+}
+
+impl PikeType {
+    // Converts a type node and its children. Caller must hold the interpreter
+    // lock.
+    unsafe fn from_node(node: *mut pike_type) -> Self {
+        if node.is_null() {
+            return PikeType::Mixed;
+        }
+        let car = (*node).car;
+        let cdr = (*node).cdr;
+        match u32::from((*node).type_) {
+            PIKE_T_INT => {
+                // The bounds are stored directly in car and cdr.
+                PikeType::Int(IntRange::from_bounds(car as isize as i32,
+                    cdr as isize as i32))
+            },
+            PIKE_T_FLOAT => PikeType::Float,
+            PIKE_T_STRING => {
+                match PikeType::from_node(car) {
+                    PikeType::Int(range) => PikeType::String(range),
+                    _ => PikeType::String(IntRange::full())
+                }
+            },
+            PIKE_T_ARRAY => PikeType::Array(Box::new(PikeType::from_node(car))),
+            PIKE_T_MAPPING => {
+                PikeType::Mapping(Box::new(PikeType::from_node(car)),
+                    Box::new(PikeType::from_node(cdr)))
+            },
+            PIKE_T_MULTISET => {
+                PikeType::Multiset(Box::new(PikeType::from_node(car)))
+            },
+            PIKE_T_FUNCTION | T_MANY => {
+                PikeType::Function(FunctionType::from_node(node))
+            },
+            PIKE_T_OBJECT => PikeType::Object(ObjectType::from_node(node)),
+            PIKE_T_PROGRAM => {
+                match PikeType::from_node(car) {
+                    PikeType::Object(obj_type) => PikeType::Program(obj_type),
+                    _ => PikeType::Program(ObjectType::Any)
+                }
+            },
+            PIKE_T_TYPE => PikeType::Type(Box::new(PikeType::from_node(car))),
+            T_OR => {
+                let mut types = Vec::new();
+                PikeType::from_node(car).flatten_into(&mut types, false);
+                PikeType::from_node(cdr).flatten_into(&mut types, false);
+                PikeType::Or(types)
+            },
+            T_AND => {
+                let mut types = Vec::new();
+                PikeType::from_node(car).flatten_into(&mut types, true);
+                PikeType::from_node(cdr).flatten_into(&mut types, true);
+                PikeType::And(types)
+            },
+            T_NOT => PikeType::Not(Box::new(PikeType::from_node(car))),
+            PIKE_T_ZERO => PikeType::Zero,
+            T_VOID => PikeType::Void,
+            // Names, attributes, scopes and marker assignments only annotate
+            // the actual type, which is in cdr.
+            PIKE_T_NAME | PIKE_T_ATTRIBUTE | PIKE_T_SCOPE | T_ASSIGN => {
+                PikeType::from_node(cdr)
+            },
+            // Markers ('0'-'9') and anything we don't know about.
+            _ => PikeType::Mixed
+        }
+    }
+
+    /// Returns the type of a value, like Pike's `typeof()`.
+    pub fn of(thing: &PikeThing, ctx: &PikeContext) -> Self {
+        PikeType::from_with_ctx(&PikeTypeRef::of(thing, ctx), ctx)
+    }
+
+    /// Returns true if every value of this type is also a value of the other
+    /// type. Fails if either type can't be converted to a Pike type.
+    pub fn is_subtype_of(&self, other: &PikeType, ctx: &PikeContext)
+    -> Result<bool, PikeError> {
+        let a = PikeTypeRef::try_from_with_ctx(self, ctx)?;
+        let b = PikeTypeRef::try_from_with_ctx(other, ctx)?;
+        Ok(a.is_subtype_of(&b, ctx))
+    }
+
+    /// Returns true if some value of this type is also a value of the other
+    /// type. Fails if either type can't be converted to a Pike type.
+    pub fn matches(&self, other: &PikeType, ctx: &PikeContext)
+    -> Result<bool, PikeError> {
+        let a = PikeTypeRef::try_from_with_ctx(self, ctx)?;
+        let b = PikeTypeRef::try_from_with_ctx(other, ctx)?;
+        Ok(a.matches(&b, ctx))
+    }
+
+    // Returns a copy where named object types are replaced by the ids of the
+    // programs they refer to, since Pike's type parser only accepts ids.
+    fn resolve_names(&self, ctx: &PikeContext) -> Result<PikeType, PikeError> {
+        let conv = |t: &PikeType| t.resolve_names(ctx).map(Box::new);
+        let conv_all = |types: &Vec<PikeType>| {
+            types.iter().map(|t| t.resolve_names(ctx)).collect::<Result<_, _>>()
+        };
+        Ok(match self {
+            PikeType::Array(elem) => PikeType::Array(conv(elem)?),
+            PikeType::Mapping(key, val) => {
+                PikeType::Mapping(conv(key)?, conv(val)?)
+            },
+            PikeType::Multiset(elem) => PikeType::Multiset(conv(elem)?),
+            PikeType::Function(func_type) => {
+                PikeType::Function(FunctionType {
+                    args: conv_all(&func_type.args)?,
+                    rest: match func_type.rest {
+                        Some(ref r) => Some(conv(r)?),
+                        None => None
+                    },
+                    ret: conv(&func_type.ret)?
+                })
+            },
+            PikeType::Object(ObjectType::Named(name)) => {
+                PikeType::Object(ObjectType::Is(resolve_program_id(name, ctx)?))
+            },
+            PikeType::Program(ObjectType::Named(name)) => {
+                PikeType::Program(ObjectType::Is(resolve_program_id(name, ctx)?))
+            },
+            PikeType::Type(t) => PikeType::Type(conv(t)?),
+            PikeType::Or(types) => PikeType::Or(conv_all(types)?),
+            PikeType::And(types) => PikeType::And(conv_all(types)?),
+            PikeType::Not(t) => PikeType::Not(conv(t)?),
+            t => t.clone()
+        })
+    }
+
+    // Pushes the type to a list of union (or intersection, if `and` is set)
+    // members, flattening nested unions (intersections).
+    pub(crate) fn flatten_into(self, types: &mut Vec<PikeType>, and: bool) {
+        match self {
+            PikeType::Or(members) if !and => types.extend(members),
+            PikeType::And(members) if and => types.extend(members),
+            t => types.push(t)
+        }
+    }
+}
+
+// Returns the id of the program that a name such as "Stdio.File" refers to. A
+// name that refers to an object (e.g. a module) gives the id of its program,
+// like in Pike's compiler.
+fn resolve_program_id(name: &str, ctx: &PikeContext) -> Result<i32, PikeError> {
+    let program_ref = match ctx.resolv(name)? {
+        PikeThing::Program(program_ref) => program_ref,
+        PikeThing::Object(object_ref) => {
+            let object: PikeObject<()> = object_ref.into_with_ctx(ctx);
+            match object.program() {
+                Some(program) => (&program).into(),
+                None => {
+                    return Err(PikeError::Args(
+                        format!("{} refers to a destructed object.", name)));
+                }
+            }
+        },
+        _ => {
+            return Err(PikeError::Args(
+                format!("{} doesn't refer to a program.", name)));
+        }
+    };
+    Ok(program_ref.id())
+}
+
+impl FunctionType {
+    // Walks the argument chain of a function type node.
+    unsafe fn from_node(mut node: *mut pike_type) -> Self {
+        let mut args = Vec::new();
+        while !node.is_null() && u32::from((*node).type_) == PIKE_T_FUNCTION {
+            args.push(PikeType::from_node((*node).car));
+            node = (*node).cdr;
+        }
+        if node.is_null() || u32::from((*node).type_) != T_MANY {
+            return FunctionType {
+                args,
+                rest: None,
+                ret: Box::new(PikeType::Mixed)
+            };
+        }
+        let rest = match PikeType::from_node((*node).car) {
+            PikeType::Void => None,
+            rest => Some(Box::new(rest))
+        };
+        FunctionType {
+            args,
+            rest,
+            ret: Box::new(PikeType::from_node((*node).cdr))
+        }
+    }
+
+    fn is_any(&self) -> bool {
+        self.args.is_empty() && *self.ret == PikeType::Mixed &&
+            self.rest.as_ref().map(|r| **r == PikeType::Mixed).unwrap_or(false)
+    }
+}
+
+impl ObjectType {
+    // car is 1 for "is" and 0 for "implements", cdr is the program id, or 0
+    // for any object.
+    unsafe fn from_node(node: *mut pike_type) -> Self {
+        let id = (*node).cdr as isize as i32;
+        if id == 0 {
+            ObjectType::Any
+        } else if !(*node).car.is_null() {
+            ObjectType::Is(id)
+        } else {
+            ObjectType::Implements(id)
+        }
+    }
+}
+
+impl<'a, 'ctx> FromWithCtx<'ctx, &'a PikeTypeRef> for PikeType {
+    fn from_with_ctx(type_ref: &'a PikeTypeRef, _ctx: &'ctx PikeContext)
+    -> Self {
+        unsafe { PikeType::from_node(type_ref.ptr) }
+    }
+}
+
+impl fmt::Display for IntRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(min) = self.min {
+            write!(f, "{}", min)?;
+        }
+        write!(f, "..")?;
+        if let Some(max) = self.max {
+            write!(f, "{}", max)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_any() {
+            return write!(f, "function");
+        }
+        let mut args: Vec<String> =
+            self.args.iter().map(|arg| arg.to_string()).collect();
+        if let Some(ref rest) = self.rest {
+            args.push(format!("{} ...", rest));
+        }
+        write!(f, "function({} : {})", args.join(", "), self.ret)
+    }
+}
+
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectType::Any => Ok(()),
+            ObjectType::Is(id) => write!(f, "is {}", id),
+            ObjectType::Implements(id) => write!(f, "implements {}", id),
+            ObjectType::Named(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+impl fmt::Display for PikeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PikeType::Int(range) if range.is_full() => write!(f, "int"),
+            PikeType::Int(range) => write!(f, "int({})", range),
+            PikeType::Float => write!(f, "float"),
+            PikeType::String(range) if range.is_full() => write!(f, "string"),
+            PikeType::String(range) => write!(f, "string({})", range),
+            PikeType::Array(elem) => {
+                match **elem {
+                    PikeType::Mixed => write!(f, "array"),
+                    _ => write!(f, "array({})", elem)
+                }
+            },
+            PikeType::Mapping(key, val) => {
+                match (&**key, &**val) {
+                    (PikeType::Mixed, PikeType::Mixed) => write!(f, "mapping"),
+                    _ => write!(f, "mapping({}:{})", key, val)
+                }
+            },
+            PikeType::Multiset(elem) => {
+                match **elem {
+                    PikeType::Mixed => write!(f, "multiset"),
+                    _ => write!(f, "multiset({})", elem)
+                }
+            },
+            PikeType::Function(func_type) => write!(f, "{}", func_type),
+            PikeType::Object(ObjectType::Any) => write!(f, "object"),
+            PikeType::Object(obj_type) => write!(f, "object({})", obj_type),
+            PikeType::Program(ObjectType::Any) => write!(f, "program"),
+            // Pike describes program types by the type of their instances.
+            PikeType::Program(obj_type) =>
+                write!(f, "program(object({}))", obj_type),
+            PikeType::Type(t) => {
+                match **t {
+                    PikeType::Mixed => write!(f, "type"),
+                    _ => write!(f, "type({})", t)
+                }
+            },
+            PikeType::Or(types) => {
+                let members: Vec<String> =
+                    types.iter().map(|t| t.to_string()).collect();
+                write!(f, "{}", members.join("|"))
+            },
+            PikeType::And(types) => {
+                let members: Vec<String> = types.iter().map(|t| {
+                    match t {
+                        PikeType::Or(_) => format!("({})", t),
+                        _ => t.to_string()
+                    }
+                }).collect();
+                write!(f, "{}", members.join("&"))
+            },
+            PikeType::Not(t) => {
+                match **t {
+                    PikeType::Or(_) | PikeType::And(_) => write!(f, "!({})", t),
+                    _ => write!(f, "!{}", t)
+                }
+            },
+            PikeType::Mixed => write!(f, "mixed"),
+            PikeType::Void => write!(f, "void"),
+            PikeType::Zero => write!(f, "zero")
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use ::types::type_deps::*;
+use ::std::str::FromStr;
+
+/// Parses Pike type syntax, e.g. "function(int, string|void : array(int))".
+impl FromStr for PikeType {
+    type Err = PikeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { chars: s.chars().collect(), pos: 0 };
+        let res = parser.parse_type()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("Unexpected input after type"));
+        }
+        Ok(res)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+impl Parser {
+    // type := and_type ('|' and_type)*
+    fn parse_type(&mut self) -> Result<PikeType, PikeError> {
+        let mut types = Vec::new();
+        self.parse_and_type()?.flatten_into(&mut types, false);
+        while self.eat('|') {
+            self.parse_and_type()?.flatten_into(&mut types, false);
+        }
+        Ok(if types.len() == 1 { types.remove(0) } else { PikeType::Or(types) })
+    }
+
+    // and_type := not_type ('&' not_type)*
+    fn parse_and_type(&mut self) -> Result<PikeType, PikeError> {
+        let mut types = Vec::new();
+        self.parse_not_type()?.flatten_into(&mut types, true);
+        while self.eat('&') {
+            self.parse_not_type()?.flatten_into(&mut types, true);
+        }
+        Ok(if types.len() == 1 { types.remove(0) } else { PikeType::And(types) })
+    }
+
+    // not_type := '!' not_type | primary
+    fn parse_not_type(&mut self) -> Result<PikeType, PikeError> {
+        if self.eat('!') {
+            return Ok(PikeType::Not(Box::new(self.parse_not_type()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<PikeType, PikeError> {
+        if self.eat('(') {
+            let res = self.parse_type()?;
+            self.expect(')')?;
+            return Ok(res);
+        }
+        let name = self.parse_identifier()?;
+        let res = match name.as_str() {
+            "int" => PikeType::Int(self.parse_opt_range()?),
+            "string" => PikeType::String(self.parse_opt_range()?),
+            "float" => PikeType::Float,
+            "mixed" => PikeType::Mixed,
+            "void" => PikeType::Void,
+            "zero" => PikeType::Zero,
+            "array" => PikeType::Array(Box::new(self.parse_opt_elem()?)),
+            "multiset" => PikeType::Multiset(Box::new(self.parse_opt_elem()?)),
+            "type" => PikeType::Type(Box::new(self.parse_opt_elem()?)),
+            "mapping" => {
+                if self.eat('(') {
+                    let key = self.parse_type()?;
+                    self.expect(':')?;
+                    let val = self.parse_type()?;
+                    self.expect(')')?;
+                    PikeType::Mapping(Box::new(key), Box::new(val))
+                } else {
+                    PikeType::Mapping(Box::new(PikeType::Mixed),
+                        Box::new(PikeType::Mixed))
+                }
+            },
+            "function" => PikeType::Function(self.parse_function()?),
+            "object" => PikeType::Object(self.parse_opt_object_type()?),
+            "program" => PikeType::Program(self.parse_opt_program_type()?),
+            // Any other name refers to a program, e.g. "Stdio.File".
+            _ => PikeType::Object(ObjectType::Named(self.parse_path(name)?))
+        };
+        Ok(res)
+    }
+
+    // Parses an optional "(type)" suffix, defaulting to mixed.
+    fn parse_opt_elem(&mut self) -> Result<PikeType, PikeError> {
+        if !self.eat('(') {
+            return Ok(PikeType::Mixed);
+        }
+        let res = self.parse_type()?;
+        self.expect(')')?;
+        Ok(res)
+    }
+
+    // Parses an optional range suffix: "(min..max)" where either bound may be
+    // left out, "(n)" or "(<n>bit)".
+    fn parse_opt_range(&mut self) -> Result<IntRange, PikeError> {
+        if !self.eat('(') {
+            return Ok(IntRange::full());
+        }
+        let min = self.parse_opt_number()?;
+        let res = if self.eat_str("..") {
+            IntRange::new(min, self.parse_opt_number()?)
+        } else if self.eat_str("bit") {
+            let bits = min.ok_or_else(|| self.error("Expected number of bits"))?;
+            if bits < 1 || bits > 31 {
+                return Err(self.error("Unsupported number of bits"));
+            }
+            IntRange::new(Some(0), Some(((1i64 << bits) - 1) as i32))
+        } else {
+            let val = min.ok_or_else(|| self.error("Expected range"))?;
+            IntRange::new(Some(val), Some(val))
+        };
+        self.expect(')')?;
+        Ok(res)
+    }
+
+    // function := 'function' ['(' [arg (',' arg)*] ':' type ')']
+    // arg := type ['...']
+    fn parse_function(&mut self) -> Result<FunctionType, PikeError> {
+        if !self.eat('(') {
+            return Ok(FunctionType {
+                args: Vec::new(),
+                rest: Some(Box::new(PikeType::Mixed)),
+                ret: Box::new(PikeType::Mixed)
+            });
+        }
+        let mut args = Vec::new();
+        let mut rest = None;
+        if !self.eat(':') {
+            loop {
+                let arg = self.parse_type()?;
+                if self.eat_str("...") {
+                    // A void rest type means that there are no remaining
+                    // arguments, like in types from Pike.
+                    if arg != PikeType::Void {
+                        rest = Some(Box::new(arg));
+                    }
+                    self.expect(':')?;
+                    break;
+                }
+                args.push(arg);
+                if self.eat(':') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+        let ret = self.parse_type()?;
+        self.expect(')')?;
+        Ok(FunctionType { args, rest, ret: Box::new(ret) })
+    }
+
+    // Parses an optional "(is <id>)", "(implements <id>)" or "(<name>)"
+    // suffix.
+    fn parse_opt_object_type(&mut self) -> Result<ObjectType, PikeError> {
+        if !self.eat('(') {
+            return Ok(ObjectType::Any);
+        }
+        let name = self.parse_identifier()?;
+        let res = match name.as_str() {
+            "is" | "implements" => {
+                let id = self.parse_opt_number()?
+                    .ok_or_else(|| self.error("Expected program id"))?;
+                if name == "is" {
+                    ObjectType::Is(id)
+                } else {
+                    ObjectType::Implements(id)
+                }
+            },
+            _ => ObjectType::Named(self.parse_path(name)?)
+        };
+        self.expect(')')?;
+        Ok(res)
+    }
+
+    // Parses an optional "(object(...))" suffix, as Pike describes program
+    // types. The shorter suffixes of object types are accepted too, e.g.
+    // "program(Stdio.File)".
+    fn parse_opt_program_type(&mut self) -> Result<ObjectType, PikeError> {
+        let start = self.pos;
+        if self.eat('(') &&
+            self.parse_identifier().map(|name| name == "object").unwrap_or(false) {
+            let res = self.parse_opt_object_type()?;
+            self.expect(')')?;
+            return Ok(res);
+        }
+        self.pos = start;
+        self.parse_opt_object_type()
+    }
+
+    // Parses the remaining components of a dotted name. A dot that isn't
+    // followed by an identifier (like the one starting "...") ends the path.
+    fn parse_path(&mut self, first: String) -> Result<String, PikeError> {
+        let mut path = first;
+        loop {
+            self.skip_whitespace();
+            let continues = self.peek() == Some('.') &&
+                self.chars[self.pos + 1..].iter()
+                    .find(|c| !c.is_whitespace())
+                    .map(|&c| c.is_alphabetic() || c == '_')
+                    .unwrap_or(false);
+            if !continues {
+                break;
+            }
+            self.pos += 1;
+            path.push('.');
+            path.push_str(&self.parse_identifier()?);
+        }
+        Ok(path)
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, PikeError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.pos < self.chars.len() &&
+            (self.chars[self.pos].is_alphanumeric() || self.chars[self.pos] == '_') {
+            self.pos += 1;
+        }
+        if self.pos == start || self.chars[start].is_numeric() {
+            self.pos = start;
+            return Err(self.error("Expected type"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn parse_opt_number(&mut self) -> Result<Option<i32>, PikeError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        while self.peek().map(|c| c.is_digit(10)).unwrap_or(false) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        if digits.is_empty() || digits == "-" {
+            self.pos = start;
+            return Ok(None);
+        }
+        digits.parse::<i32>().map(Some).map_err(|_| {
+            self.pos = start;
+            self.error("Integer out of range")
+        })
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    // Consumes the character if it's next, after any whitespace.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        self.skip_whitespace();
+        let len = s.chars().count();
+        let matches = self.pos + len <= self.chars.len() &&
+            self.chars[self.pos..self.pos + len].iter().cloned().eq(s.chars());
+        if matches {
+            self.pos += len;
+        }
+        matches
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), PikeError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", c)))
+        }
+    }
+
+    fn error(&self, msg: &str) -> PikeError {
+        PikeError::Args(format!("{} at position {} in type string.",
+            msg, self.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> PikeType {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_basic_types() {
+        assert_eq!(parse("int"), PikeType::Int(IntRange::full()));
+        assert_eq!(parse("int(0..255)"),
+            PikeType::Int(IntRange::new(Some(0), Some(255))));
+        assert_eq!(parse("int(8bit)"),
+            PikeType::Int(IntRange::new(Some(0), Some(255))));
+        assert_eq!(parse("int(..-1)"),
+            PikeType::Int(IntRange::new(None, Some(-1))));
+        assert_eq!(parse("string(7)"),
+            PikeType::String(IntRange::new(Some(7), Some(7))));
+        assert_eq!(parse(" array ( float ) "),
+            PikeType::Array(Box::new(PikeType::Float)));
+        assert_eq!(parse("mapping(string:mixed)"),
+            PikeType::Mapping(Box::new(PikeType::String(IntRange::full())),
+                Box::new(PikeType::Mixed)));
+    }
+
+    #[test]
+    fn parse_or_and_not() {
+        assert_eq!(parse("int|float|string"), PikeType::Or(vec![
+            PikeType::Int(IntRange::full()), PikeType::Float,
+            PikeType::String(IntRange::full())]));
+        assert_eq!(parse("(int|float)|void"), PikeType::Or(vec![
+            PikeType::Int(IntRange::full()), PikeType::Float,
+            PikeType::Void]));
+        assert_eq!(parse("!zero&mixed"), PikeType::And(vec![
+            PikeType::Not(Box::new(PikeType::Zero)), PikeType::Mixed]));
+    }
+
+    #[test]
+    fn parse_object_types() {
+        assert_eq!(parse("object"), PikeType::Object(ObjectType::Any));
+        assert_eq!(parse("object(is 17)"), PikeType::Object(ObjectType::Is(17)));
+        assert_eq!(parse("object(implements 17)"),
+            PikeType::Object(ObjectType::Implements(17)));
+        assert_eq!(parse("Stdio.File"),
+            PikeType::Object(ObjectType::Named("Stdio.File".to_string())));
+        assert_eq!(parse("program(object(is 17))"),
+            PikeType::Program(ObjectType::Is(17)));
+        assert_eq!(parse("program(Stdio.File)"),
+            PikeType::Program(ObjectType::Named("Stdio.File".to_string())));
+    }
+
+    #[test]
+    fn parse_function_types() {
+        assert_eq!(parse("function(int, string|void : float)"),
+            PikeType::Function(FunctionType {
+                args: vec![PikeType::Int(IntRange::full()),
+                    PikeType::Or(vec![PikeType::String(IntRange::full()),
+                        PikeType::Void])],
+                rest: None,
+                ret: Box::new(PikeType::Float)
+            }));
+        assert_eq!(parse("function(Foo ...:void)"),
+            PikeType::Function(FunctionType {
+                args: vec![],
+                rest: Some(Box::new(
+                    PikeType::Object(ObjectType::Named("Foo".to_string())))),
+                ret: Box::new(PikeType::Void)
+            }));
+        assert_eq!(parse("function(int, void ... : void)"),
+            parse("function(int : void)"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &["", "int(", "int(40bit)", "mapping(int)", "function(int)",
+            "object(is)", "int float", "1int"] {
+            assert!(s.parse::<PikeType>().is_err(), "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in &["int", "int(0..255)", "int(..-1)", "string(0..127)",
+            "float", "mixed", "void", "zero", "array(int)",
+            "mapping(string : array(float))", "multiset(string)", "type(int)",
+            "object", "object(is 17)", "object(implements 17)", "Stdio.File",
+            "program", "program(object(is 17))", "function",
+            "function(int, float ... : string)", "function( : void)",
+            "int|string|void", "!zero&mixed", "array(int|string)"] {
+            let t = parse(s);
+            assert_eq!(parse(&t.to_string()), t, "{:?} => {}", s, t);
+        }
+    }
+}
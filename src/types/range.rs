@@ -0,0 +1,32 @@
+use ::interpreter::PikeError;
+use ::std::ops::{Bound, RangeBounds};
+
+// Resolves a range against a sequence of the specified length, returning the
+// start and end indices. The name of the sequence is used in the error
+// returned when the range is out of bounds.
+pub fn resolve_range<R>(range: &R, len: usize, name: &str)
+    -> Result<(usize, usize), PikeError>
+where R: RangeBounds<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&i) => Some(i),
+        Bound::Excluded(&i) => i.checked_add(1),
+        Bound::Unbounded => Some(0)
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i.checked_add(1),
+        Bound::Excluded(&i) => Some(i),
+        Bound::Unbounded => Some(len)
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end && end <= len =>
+            Ok((start, end)),
+        (Some(start), Some(end)) if len == 0 => Err(PikeError::Args(format!(
+            "Range {}..{} is out of bounds, the {} is empty.", start, end, name))),
+        _ if len == 0 => Err(PikeError::Args(format!(
+            "Range is out of bounds, the {} is empty.", name))),
+        (Some(start), Some(end)) => Err(PikeError::Args(format!(
+            "Range {}..{} is out of {} range 0..{}.", start, end, name, len))),
+        _ => Err(PikeError::Args(format!(
+            "Range is out of {} range 0..{}.", name, len)))
+    }
+}